@@ -0,0 +1,268 @@
+use crate::{
+    get_inputs, get_neuron, get_renderer, get_scene, get_stages,
+    render::{hash, write_stack, Image, Renderer},
+    Args,
+};
+use rand::{rngs::StdRng, RngExt, SeedableRng};
+use std::{collections::HashSet, fmt::Write, fs, path::Path};
+
+#[derive(clap::Args, Debug)]
+pub struct DatasetArgs {
+    #[arg(long, value_delimiter = ',', default_values_t = [64, 64, 64])]
+    patch: Vec<u32>,
+
+    #[arg(long, default_value_t = String::from("random"))]
+    sampling: String,
+
+    #[arg(long, default_value_t = 10)]
+    count: usize,
+
+    #[arg(long, default_value_t = 0.0)]
+    min_foreground: f32,
+
+    #[arg(long, default_value_t = false)]
+    image: bool,
+
+    #[arg(long, default_value_t = String::from("csv"))]
+    manifest: String,
+}
+
+/// Random patches drawn per patch kept, before giving up on a neuron.
+const MAX_TRIES: usize = 100;
+
+/// A patch of the dataset, cropped from the stack of a neuron.
+struct Patch {
+    mask: String,
+    image: Option<String>,
+    source: String,
+    /// Voxel of the stack at the patch corner, rows counted from the top.
+    origin: [u32; 3],
+    /// Fraction of nonzero voxels of the mask.
+    foreground: f32,
+}
+
+/// Render each input neuron on its own, the mask as asked by the render
+/// options and the image as intensities through the PSF and noise stages,
+/// then crop both into patches of the same voxels. Patches are named after
+/// the index of their input, so inputs of the same name do not collide, and
+/// images are seeded by it, so their noise is not repeated.
+pub fn generate(args: &Args, dataset: &DatasetArgs) {
+    let [pw, ph, pd] = dataset.patch[..] else {
+        panic!("invalid patch")
+    };
+    if args.save_swc.is_some() {
+        panic!("save swc does not support dataset");
+    }
+    let out = args.output.as_deref().unwrap(); // required
+    fs::create_dir_all(out).expect("fails to create output dir");
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut patches = vec![];
+    for (i, input) in get_inputs(args).into_iter().enumerate() {
+        let neurons = [get_neuron(args, &input)];
+        let mask: Vec<_> = get_renderer(args, get_scene(args, &neurons))
+            .image_stack()
+            .collect();
+        let image = dataset.image.then(|| {
+            let args = Args {
+                mode: String::from("solid_color"),
+                color: String::from("luma"),
+                channels: vec![],
+                seed: args.seed ^ hash(i as u64),
+                ..args.clone()
+            };
+            let renderer = get_stages(&args, get_renderer(&args, get_scene(&args, &neurons)));
+            renderer.image_stack().collect::<Vec<_>>()
+        });
+
+        let Some(first) = mask.first() else {
+            println!("skip {input}: empty stack");
+            continue;
+        };
+        let size = [first.width(), first.height(), mask.len() as u32];
+        if size[0] < pw || size[1] < ph || size[2] < pd {
+            println!("skip {input}: stack smaller than the patches");
+            continue;
+        }
+
+        let candidates: Box<dyn Iterator<Item = [u32; 3]> + '_> = match dataset.sampling.as_str() {
+            "random" => {
+                let rng = &mut rng;
+                let mut origin = move || {
+                    let [x, y, z] = [(size[0], pw), (size[1], ph), (size[2], pd)];
+                    [x, y, z].map(|(n, p)| rng.random_range(0..=n - p))
+                };
+                let tries = dataset.count * MAX_TRIES;
+                Box::new((0..tries).map(move |_| origin()))
+            }
+            "tiled" => Box::new(tiles(size, [pw, ph, pd]).into_iter()),
+            _ => panic!("invalid sampling"),
+        };
+        let limit = match dataset.sampling.as_str() {
+            "random" => dataset.count,
+            _ => usize::MAX,
+        };
+
+        let stem = Path::new(&input).file_stem().unwrap().to_string_lossy();
+        let stem = format!("{i:04}_{stem}");
+        let mut used = HashSet::new();
+        let mut k = 0;
+        for [x, y, z] in candidates {
+            if k >= limit {
+                break;
+            }
+            if !used.insert([x, y, z]) {
+                continue; // drawn again
+            }
+            let crop = |stack: &[Image]| {
+                let slices = stack[z as usize..(z + pd) as usize].iter();
+                slices.map(|a| a.crop(x, y, pw, ph)).collect::<Vec<_>>()
+            };
+            let mask_patch = crop(&mask);
+            let foreground = foreground(&mask_patch);
+            if foreground < dataset.min_foreground {
+                continue;
+            }
+
+            let name = format!("{stem}_{k:04}_mask.tif");
+            write_stack(&format!("{out}/{name}"), mask_patch.into_iter())
+                .expect("fails to write patch");
+            let image_name = image.as_ref().map(|image| {
+                let name = format!("{stem}_{k:04}_image.tif");
+                write_stack(&format!("{out}/{name}"), crop(image).into_iter())
+                    .expect("fails to write patch");
+                name
+            });
+            patches.push(Patch {
+                mask: name,
+                image: image_name,
+                source: input.clone(),
+                origin: [x, y, z],
+                foreground,
+            });
+            k += 1;
+        }
+        if args.verbose {
+            println!("{input}: {k} patches");
+        }
+    }
+
+    let manifest = match dataset.manifest.as_str() {
+        "csv" => to_csv(&patches),
+        "json" => to_json(&patches),
+        _ => panic!("invalid manifest"),
+    };
+    let fname = format!("{out}/manifest.{}", dataset.manifest);
+    fs::write(fname, manifest).expect("fails to write manifest");
+}
+
+/// Origins of the patches tiling the stack, the last ones along each axis
+/// moved back to fit in it.
+fn tiles(size: [u32; 3], patch: [u32; 3]) -> Vec<[u32; 3]> {
+    let [xs, ys, zs] = [0, 1, 2].map(|a| {
+        let mut origins: Vec<_> = (0..=size[a] - patch[a])
+            .step_by(patch[a] as usize)
+            .collect();
+        if origins.last() != Some(&(size[a] - patch[a])) {
+            origins.push(size[a] - patch[a]);
+        }
+        origins
+    });
+    let mut tiles = vec![];
+    for &z in zs.iter() {
+        for &y in ys.iter() {
+            tiles.extend(xs.iter().map(|&x| [x, y, z]));
+        }
+    }
+    tiles
+}
+
+fn foreground(stack: &[Image]) -> f32 {
+    let (mut count, mut total) = (0, 0);
+    for img in stack {
+        let channels = img.channels();
+        let len = channels[0].len();
+        count += (0..len)
+            .filter(|&i| channels.iter().any(|c| c[i] != 0.0))
+            .count();
+        total += len;
+    }
+    count as f32 / total as f32
+}
+
+fn to_csv(patches: &[Patch]) -> String {
+    let quote = |a: &str| match a.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", a.replace('"', "\"\"")),
+        false => a.to_string(),
+    };
+    let mut csv = String::from("mask,image,source,x,y,z,foreground\n");
+    for p in patches {
+        let [x, y, z] = p.origin;
+        let image = p.image.as_deref().unwrap_or_default();
+        let (mask, image, source) = (quote(&p.mask), quote(image), quote(&p.source));
+        writeln!(csv, "{mask},{image},{source},{x},{y},{z},{}", p.foreground).unwrap();
+    }
+    csv
+}
+
+fn to_json(patches: &[Patch]) -> String {
+    let quote = |a: &str| {
+        let mut quoted = String::from("\"");
+        for c in a.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+                c => quoted.push(c),
+            }
+        }
+        quoted + "\""
+    };
+    let entries: Vec<_> = patches
+        .iter()
+        .map(|p| {
+            let [x, y, z] = p.origin;
+            let image = p.image.as_deref().map_or(String::from("null"), quote);
+            format!(
+                "  {{\"mask\": {}, \"image\": {image}, \"source\": {}, \"origin\": [{x}, {y}, {z}], \"foreground\": {}}}",
+                quote(&p.mask),
+                quote(&p.source),
+                p.foreground
+            )
+        })
+        .collect();
+    match entries.is_empty() {
+        true => String::from("[]\n"),
+        false => format!("[\n{}\n]\n", entries.join(",\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiled_patches() {
+        let tiles = tiles([10, 4, 3], [4, 4, 3]);
+        assert_eq!(tiles, vec![[0, 0, 0], [4, 0, 0], [6, 0, 0]]);
+    }
+
+    #[test]
+    fn manifests() {
+        let patches = [Patch {
+            mask: String::from("0000_a_0000_mask.tif"),
+            image: None,
+            source: String::from("dir/a,\"b\".swc"),
+            origin: [1, 2, 3],
+            foreground: 0.5,
+        }];
+        assert_eq!(
+            to_csv(&patches),
+            "mask,image,source,x,y,z,foreground\n0000_a_0000_mask.tif,,\"dir/a,\"\"b\"\".swc\",1,2,3,0.5\n"
+        );
+        assert_eq!(
+            to_json(&patches),
+            "[\n  {\"mask\": \"0000_a_0000_mask.tif\", \"image\": null, \"source\": \"dir/a,\\\"b\\\".swc\", \"origin\": [1, 2, 3], \"foreground\": 0.5}\n]\n"
+        );
+    }
+}
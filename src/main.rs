@@ -1,3 +1,4 @@
+mod dataset;
 mod neuron;
 mod render;
 mod sdf;
@@ -8,39 +9,136 @@ mod vec;
 extern crate lazy_static;
 
 use crate::{
-    neuron::{Node, SWC},
-    render::{ImageStackRenderer, Msaa, ObjectsScene, Renderer, Scene, TiffWriter},
+    dataset::DatasetArgs,
+    neuron::{ParseMode, SWC},
+    render::{
+        Coverage, Depth, ImageStackRenderer, Msaa, NoiseRenderer, Object, ObjectsScene, Overlap,
+        Pattern, Psf, PsfRenderer, RenderMode, Renderer, Scene, TiffWriter,
+    },
     vec::Vec3f,
 };
-use clap::Parser;
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use clap::{Parser, Subcommand};
+use std::{fs, path::Path, sync::Arc};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render each neuron on its own, and crop patches of its mask and
+    /// synthetic image, listed in a manifest.
+    Dataset {
+        #[command(flatten)]
+        args: Args,
+
+        #[command(flatten)]
+        dataset: DatasetArgs,
+    },
+}
+
+#[derive(clap::Args, Clone, Debug)]
 struct Args {
-    input: String,
+    #[arg(required = true)]
+    input: Vec<String>,
 
-    #[arg(short, long)]
-    output: String,
+    #[arg(short, long, required = true)]
+    output: Option<String>,
 
     #[arg(long, default_value_t = String::from("solid_color"))]
     mode: String,
 
     #[arg(long, default_value_t = 1)]
-    msaa: i32,
+    msaa: usize,
+
+    #[arg(long, default_value_t = String::from("grid"))]
+    pattern: String,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    #[arg(long, default_value_t = String::from("samples"))]
+    coverage: String,
+
+    #[arg(long)]
+    depth: Option<String>,
+
+    #[arg(long, default_value_t = String::from("luma"))]
+    color: String,
+
+    #[arg(long, value_delimiter = ',')]
+    channels: Vec<u32>,
+
+    #[arg(long, default_value_t = false)]
+    unsigned: bool,
+
+    #[arg(long)]
+    truncate: Option<f32>,
+
+    #[arg(long)]
+    psf: Option<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    psf_sigma: Vec<f32>,
+
+    #[arg(long)]
+    photons: Option<f32>,
+
+    #[arg(long)]
+    read_noise: Option<f32>,
+
+    #[arg(long)]
+    background: Option<f32>,
+
+    #[arg(long, default_value_t = 10.0)]
+    background_scale: f32,
+
+    #[arg(long)]
+    drift: Option<f32>,
+
+    #[arg(long)]
+    attenuation: Option<f32>,
 
     #[arg(long, default_value_t = false)]
     verbose: bool,
 
+    #[arg(long, default_value_t = false)]
+    lenient: bool,
+
     #[arg(long)]
     reset_radius: Option<f32>,
 
+    #[arg(long)]
+    save_swc: Option<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    include: Vec<i32>,
+
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<i32>,
+
+    #[arg(long, default_value_t = false)]
+    apply_scale_shift: bool,
+
+    #[arg(long, default_value_t = String::from("nearest"))]
+    overlap: String,
+
     #[arg(long)]
     node: Option<i32>,
 
     #[arg(long)]
     decay: Option<f32>,
 
+    #[arg(long)]
+    column: Option<usize>,
+
     #[arg(long)]
     align: Option<String>,
 
@@ -55,33 +153,146 @@ struct Args {
 }
 
 fn main() {
-    let args = Args::parse();
-    let neuron = get_neuron(&args);
-    let scene = get_scene(&args, neuron);
-    let renderer = get_renderer(&args, scene);
-    let w = get_writer(&args, renderer);
-    if args.output.ends_with("/") {
-        w.write_images(&args.output).expect("fails to write images")
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Dataset { args, dataset }) => dataset::generate(&args, &dataset),
+        None => render(&cli.args),
+    }
+}
+
+fn render(args: &Args) {
+    let inputs = get_inputs(args);
+    if inputs.len() > 1 && args.save_swc.is_some() {
+        panic!("save swc only support a single input");
+    }
+    if args.verbose && args.mode == "instance" {
+        for (i, input) in inputs.iter().enumerate() {
+            println!("label {}: {input}", i + 1);
+        }
+    }
+
+    let neurons: Vec<_> = inputs.iter().map(|a| get_neuron(args, a)).collect();
+    let scene = get_scene(args, &neurons);
+    let renderer = get_renderer(args, scene);
+    let renderer = get_stages(args, renderer);
+    let w = get_writer(args, renderer);
+    let output = args.output.as_deref().unwrap(); // required
+    if output.ends_with("/") {
+        w.write_images(output).expect("fails to write images")
     } else {
-        w.write_image(&args.output).expect("fails to write image")
+        w.write_image(output).expect("fails to write image")
     }
 }
 
-fn get_neuron(args: &Args) -> SWC {
+/// Input files, where directories are expanded to the neurons they contain,
+/// sorted by name, so the label of each neuron is reproducible.
+fn get_inputs(args: &Args) -> Vec<String> {
+    const EXTENSIONS: [&str; 4] = ["swc", "eswc", "asc", "nml"];
+    let mut inputs = vec![];
+    for input in args.input.iter() {
+        if !Path::new(input).is_dir() {
+            inputs.push(input.clone());
+            continue;
+        }
+
+        let mut files: Vec<_> = fs::read_dir(input)
+            .expect("fails to read dir")
+            .map(|entry| entry.expect("fails to read dir").path())
+            .filter(|path| {
+                let ext = path
+                    .extension()
+                    .and_then(|a| a.to_str())
+                    .unwrap_or_default();
+                path.is_file() && EXTENSIONS.contains(&ext.to_lowercase().as_str())
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        inputs.extend(files);
+    }
+    inputs
+}
+
+fn get_neuron(args: &Args, input: &str) -> SWC {
     if args.verbose {
-        println!("read swc: {}", args.input);
+        println!("read swc: {}", input);
+    }
+    let mut neuron = match args.lenient {
+        _ if input.to_lowercase().ends_with(".asc") => {
+            SWC::read_asc(input).expect("fails to read asc")
+        }
+        _ if input.to_lowercase().ends_with(".nml") => {
+            SWC::read_neuroml(input).expect("fails to read neuroml")
+        }
+        true => {
+            let (neuron, diagnostics) =
+                SWC::read_with_mode(input, ParseMode::Lenient).expect("fails to read swc");
+            for err in diagnostics {
+                println!("warning: {err}");
+            }
+            neuron
+        }
+        false => SWC::read(input).expect("fails to read swc"),
+    };
+    if args.verbose && !neuron.metadata().fields().is_empty() {
+        println!("{}", neuron.metadata());
+    }
+    if args.apply_scale_shift {
+        neuron.apply_scale_shift();
     }
-    let neuron = SWC::read(&args.input).expect("fails to read swc");
     if let Some(r) = args.reset_radius {
-        set_radius_dfs(&neuron.root, r);
+        neuron.map_geometry(|n| (n.xyz(), r));
+    }
+    if !args.include.is_empty() || !args.exclude.is_empty() {
+        neuron = neuron
+            .filter_structures(&args.include, &args.exclude)
+            .expect("fails to filter structures");
+    }
+    if let Some(fname) = &args.save_swc {
+        neuron.write(fname).expect("fails to write swc");
     }
     neuron
 }
 
-fn get_scene(args: &Args, neuron: SWC) -> Arc<dyn Scene> {
+fn get_scene(args: &Args, neurons: &[SWC]) -> Arc<dyn Scene> {
     let mut scene = ObjectsScene::new();
-    let sdfs = match args.mode.as_str() {
-        "solid_color" => neuron.sdf(),
+    let overlap = match args.overlap.as_str() {
+        "first" => Overlap::First,
+        "nearest" => Overlap::Nearest,
+        // only instance labels are known to stay below the overlap label
+        "separate" if args.mode != "instance" => {
+            panic!("separate overlap only support instance mode")
+        }
+        "separate" => Overlap::Label(neurons.len() as u32 + 1),
+        _ => panic!("invalid overlap"),
+    };
+    scene.set_overlap(overlap);
+
+    // neurons are added in order, so the first one wins overlaps if asked
+    for (i, neuron) in neurons.iter().enumerate() {
+        for sdf in get_sdfs(args, neuron, i as u32 + 1) {
+            scene.add(sdf);
+        }
+    }
+    scene.build_bvh();
+    Arc::new(scene)
+}
+
+fn get_sdfs(args: &Args, neuron: &SWC, instance: u32) -> Vec<Arc<dyn Object>> {
+    match args.mode.as_str() {
+        "solid_color" | "distance" => neuron.sdf(),
+        "label" => {
+            let (sdfs, skipped) = neuron.sdf_with_structures();
+            if skipped > 0 {
+                println!("warning: {skipped} nodes of undefined type are left unlabelled");
+            }
+            sdfs
+        }
+        "instance" => neuron.sdf_with_label(instance),
+        "branch" => neuron
+            .sdf_with_branch_ids()
+            .expect("fails to label branches"),
+        "node" => neuron.sdf_with_node_ids().expect("fails to label nodes"),
         "path_decay" => {
             let s = Vec3f::new(0.0, 0.0, 0.0);
             let e = Vec3f::new(1.0, 1.0, 1.0);
@@ -89,26 +300,43 @@ fn get_scene(args: &Args, neuron: SWC) -> Arc<dyn Scene> {
             let decay_fn = |a| vec::interpolate(s, e, a / decay);
             match args.node {
                 Some(id) => {
-                    let n = neuron.node(id).expect("node not found");
-                    let n = n.borrow();
-                    n.sdf_with_path_decay(decay_fn)
+                    let n = neuron.index_of(id).expect("node not found");
+                    neuron.sdf_with_path_decay_from(n, decay_fn)
                 }
                 None => neuron.sdf_with_path_decay(decay_fn),
             }
         }
+        "extra" => neuron
+            .sdf_with_extra(args.column.expect("missing column arg"))
+            .expect("fails to render column"),
         _ => panic!("invalid mode"),
-    };
-
-    for sdf in sdfs {
-        scene.add(sdf);
     }
-    scene.build_bvh();
-    Arc::new(scene)
 }
 
-fn get_renderer(args: &Args, scene: Arc<dyn Scene>) -> Box<dyn Renderer> {
+fn get_renderer(args: &Args, scene: Arc<dyn Scene>) -> ImageStackRenderer {
     let mut renderer = ImageStackRenderer::new(scene);
-    renderer.set_msaa(Msaa::try_from(args.msaa).expect("invalid msaa"));
+    let pattern = Pattern::try_from(args.pattern.as_str()).expect("invalid pattern");
+    let mut msaa = Msaa::new(args.msaa, pattern).expect("invalid msaa");
+    msaa.set_seed(args.seed);
+    renderer.set_msaa(msaa);
+    renderer.set_coverage(Coverage::try_from(args.coverage.as_str()).expect("invalid coverage"));
+    renderer.set_depth(get_depth(args));
+    let labels = is_label_mode(args);
+    let mode = match args.color.as_str() {
+        _ if labels && !args.channels.is_empty() => {
+            RenderMode::LabelChannels(args.channels.clone())
+        }
+        _ if labels => RenderMode::Label,
+        _ if args.mode == "distance" => RenderMode::Distance {
+            signed: !args.unsigned,
+            truncation: args.truncate,
+        },
+        "luma" => RenderMode::Intensity,
+        "rgb" => RenderMode::Rgb,
+        "channels" => RenderMode::ColorChannels,
+        _ => panic!("invalid color"),
+    };
+    renderer.set_mode(mode);
     if let Some(resolution) = &args.resolution {
         set_resolution(&mut renderer, resolution).unwrap();
     }
@@ -122,7 +350,83 @@ fn get_renderer(args: &Args, scene: Arc<dyn Scene>) -> Box<dyn Renderer> {
     if let Some(threads) = args.threads {
         renderer.set_num_threads(threads);
     }
-    Box::from(renderer)
+    renderer
+}
+
+fn get_depth(args: &Args) -> Depth {
+    let distance = args.mode == "distance";
+    let depth = match &args.depth {
+        Some(depth) => Depth::try_from(depth.as_str()).expect("invalid depth"),
+        None if distance => Depth::F32,
+        None => Depth::U8,
+    };
+    if distance && depth != Depth::F32 {
+        panic!("distance only support f32 depth");
+    }
+    depth
+}
+
+/// Post-render stages, turning the rendered stack into a synthetic image.
+/// They work on a f32 stack, quantized to the output depth by the last one.
+fn get_stages(args: &Args, mut renderer: ImageStackRenderer) -> Box<dyn Renderer> {
+    let psf = match (&args.psf, &args.psf_sigma[..]) {
+        (Some(fname), _) => Some(Psf::read(fname).expect("fails to read psf")),
+        (None, []) => None,
+        (None, &[x, y, z]) => Some(Psf::Gaussian(Vec3f::new(x, y, z))),
+        (None, _) => panic!("invalid psf sigma, expect 3 values: x,y,z"),
+    };
+    let noisy = args.photons.is_some()
+        || args.read_noise.is_some()
+        || args.background.is_some()
+        || args.drift.is_some()
+        || args.attenuation.is_some();
+    if psf.is_none() && !noisy {
+        return Box::new(renderer);
+    }
+
+    let depth = get_depth(args);
+    renderer.set_depth(Depth::F32);
+    let mut renderer: Box<dyn Renderer> = Box::new(renderer);
+    if let Some(psf) = psf {
+        if is_label_mode(args) {
+            panic!("psf only support intensity and color modes");
+        }
+        let mut psf = PsfRenderer::new(renderer, psf);
+        if !noisy {
+            psf.set_depth(depth);
+        }
+        renderer = Box::new(psf);
+    }
+
+    if noisy {
+        if is_label_mode(args) {
+            panic!("noise only support intensity and color modes");
+        }
+        let mut noise = NoiseRenderer::new(renderer);
+        noise.set_seed(args.seed);
+        noise.set_depth(depth);
+        if let Some(photons) = args.photons {
+            noise.set_shot_noise(photons);
+        }
+        if let Some(sigma) = args.read_noise {
+            noise.set_read_noise(sigma);
+        }
+        if let Some(level) = args.background {
+            noise.set_background(level, args.background_scale);
+        }
+        if let Some(sigma) = args.drift {
+            noise.set_drift(sigma);
+        }
+        if let Some(length) = args.attenuation {
+            noise.set_attenuation(length);
+        }
+        renderer = Box::new(noise);
+    }
+    renderer
+}
+
+fn is_label_mode(args: &Args) -> bool {
+    ["label", "instance", "branch", "node"].contains(&args.mode.as_str())
 }
 
 fn get_writer(args: &Args, renderer: Box<dyn Renderer>) -> TiffWriter {
@@ -131,14 +435,6 @@ fn get_writer(args: &Args, renderer: Box<dyn Renderer>) -> TiffWriter {
     w
 }
 
-fn set_radius_dfs(n: &Rc<RefCell<Node>>, radius: f32) {
-    let mut n = n.borrow_mut();
-    n.radius = radius;
-    for c in n.children.iter() {
-        set_radius_dfs(&c, radius)
-    }
-}
-
 fn set_resolution<'a>(
     renderer: &'a mut ImageStackRenderer,
     resolution: &'a str,
@@ -1,7 +1,12 @@
+mod asc;
 mod error_kind;
+mod filter;
+mod metadata;
+mod neuroml;
 mod node;
+mod soma;
 mod swc;
+mod traverse;
+mod validate;
 
-pub use error_kind::RootNotFoundError;
-pub use node::Node;
-pub use swc::SWC;
+pub use swc::{ParseMode, SWC};
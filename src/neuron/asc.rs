@@ -0,0 +1,263 @@
+use super::{
+    error_kind::SwcError,
+    node::{Node, APICAL, AXON, DENDRITE, SOMA},
+    swc::SWC,
+    validate::Diagnostics,
+    ParseMode,
+};
+use crate::vec::Vec3f;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// See also: https://www.neuronland.org/NLMorphologyConverter/MorphologyFormats/NeurolucidaASC/Spec.html
+
+impl SWC {
+    /// Read a Neurolucida ASC reconstruction. Soma contours become chains of
+    /// soma nodes, and every tree is attached to the nearest soma node.
+    pub fn read_asc(fname: &str) -> Result<Self, Box<dyn Error>> {
+        let fname = fs::canonicalize(PathBuf::from(fname))?;
+        let content = fs::read_to_string(&fname)?;
+        Self::parse_asc(&content, &fname)
+    }
+
+    /// Parse ASC from a string, `path` is only used to locate diagnostics.
+    pub fn parse_asc(content: &str, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let sexps = parse_sexps(content, path)?;
+
+        let mut somas = vec![];
+        let mut trees = vec![];
+        for sexp in sexps.iter() {
+            if let Sexp::List { items, .. } = sexp {
+                match structure(items) {
+                    Some(SOMA) => somas.push(items),
+                    Some(structure) => trees.push((structure, items)),
+                    None => (), // markers, contours and other annotations
+                }
+            }
+        }
+
+        let mut records: Vec<(usize, Node)> = vec![];
+        for items in somas {
+            let mut pid = -1;
+            for (line, xyz, d) in items.iter().filter_map(point) {
+                let id = records.len() as i32 + 1;
+                records.push((line, Node::new(id, SOMA, xyz, d / 2.0, pid)));
+                pid = id;
+            }
+        }
+
+        let n_soma = records.len();
+        for (structure, items) in trees {
+            // depth-first over branches, with the parent id of each section
+            let mut stack = vec![(items.as_slice(), None)];
+            while let Some((items, mut pid)) = stack.pop() {
+                for item in items.iter() {
+                    if let Some((line, xyz, d)) = point(item) {
+                        let pid = pid.get_or_insert_with(|| nearest(&records[..n_soma], xyz));
+                        let id = records.len() as i32 + 1;
+                        records.push((line, Node::new(id, structure, xyz, d / 2.0, *pid)));
+                        *pid = id;
+                    } else if let Sexp::List { items, .. } = item {
+                        if let Some(Sexp::List { .. } | Sexp::Bar) = items.first() {
+                            let branches = items.split(|a| matches!(a, Sexp::Bar));
+                            stack.extend(branches.rev().map(|branch| (branch, pid)));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut diagnostics = Diagnostics::new(ParseMode::Strict);
+        Self::build(records, path, &mut diagnostics)
+    }
+}
+
+enum Sexp {
+    Atom(String),
+    List { items: Vec<Sexp>, line: usize },
+    Bar,
+}
+
+/// Structure type of a top level list, from markers like `(Dendrite)`.
+fn structure(items: &[Sexp]) -> Option<i32> {
+    items.iter().find_map(|item| match item {
+        Sexp::List { items, .. } => match &items[..] {
+            [Sexp::Atom(a)] => match a.as_str() {
+                "CellBody" => Some(SOMA),
+                "Axon" => Some(AXON),
+                "Dendrite" => Some(DENDRITE),
+                "Apical" => Some(APICAL),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// A point is a list like `(x y z d)`, where the diameter is optional.
+fn point(sexp: &Sexp) -> Option<(usize, Vec3f, f32)> {
+    let Sexp::List { items, line } = sexp else {
+        return None;
+    };
+
+    let num = |i: usize| match items.get(i) {
+        Some(Sexp::Atom(a)) => a.parse::<f32>().ok(),
+        _ => None,
+    };
+    let xyz = Vec3f::new(num(0)?, num(1)?, num(2)?);
+    Some((*line, xyz, num(3).unwrap_or(0.0)))
+}
+
+/// Id of the nearest soma node, or -1 when there is no soma.
+fn nearest(somas: &[(usize, Node)], xyz: Vec3f) -> i32 {
+    somas
+        .iter()
+        .map(|(_, n)| (n.id, (n.xyz() - xyz).norm()))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(-1, |(id, _)| id)
+}
+
+fn parse_sexps(content: &str, path: &Path) -> Result<Vec<Sexp>, SwcError> {
+    let syntax = |line: usize, column: usize, reason: &str| SwcError::Syntax {
+        path: path.to_path_buf(),
+        line,
+        column,
+        reason: reason.to_string(),
+    };
+
+    let mut stack: Vec<(Vec<Sexp>, usize, usize)> = vec![(vec![], 0, 0)]; // (items, line, column)
+    let mut chars = content.chars().peekable();
+    let (mut line, mut column) = (1, 0);
+    let mut spines = 0; // depth of `<...>`, whose content is skipped
+    while let Some(c) = chars.next() {
+        column += 1;
+        match c {
+            '\n' => (line, column) = (line + 1, 0),
+            ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '<' => spines += 1,
+            '>' if spines == 0 => return Err(syntax(line, column, "unmatched `>`")),
+            '>' => spines -= 1,
+            _ if spines > 0 => (),
+            '(' => stack.push((vec![], line, column)),
+            ')' => {
+                let (items, start, _) = stack.pop().unwrap();
+                let Some(parent) = stack.last_mut() else {
+                    return Err(syntax(line, column, "unmatched `)`"));
+                };
+                parent.0.push(Sexp::List { items, line: start });
+            }
+            '|' => stack.last_mut().unwrap().0.push(Sexp::Bar),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(syntax(line, column, "unterminated string")),
+                    }
+                }
+                line += s.matches('\n').count();
+                column += s.chars().count() + 1;
+                stack.last_mut().unwrap().0.push(Sexp::Atom(s));
+            }
+            _ if c.is_whitespace() || c == ',' => (),
+            _ => {
+                let mut s = String::from(c);
+                while let Some(c) = chars.next_if(|&c| !is_delimiter(c)) {
+                    s.push(c);
+                }
+                column += s.chars().count() - 1;
+                stack.last_mut().unwrap().0.push(Sexp::Atom(s));
+            }
+        }
+    }
+
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap().0),
+        _ => {
+            let (_, line, column) = stack.pop().unwrap();
+            Err(syntax(line, column, "unclosed `(`"))
+        }
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '|' | ';' | '<' | '>' | '"' | ',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_asc() {
+        let content = r#"
+; Neurolucida ASC
+(ImageCoords Filename "x.jpg")
+("CellBody"
+  (Closed)
+  (Color Red)
+  (CellBody)
+  ( -1.0  0.0  0.0  0.1)  ; 1, 1
+  (  0.0  1.0  0.0  0.1)  ; 1, 2
+  (  1.0  0.0  0.0  0.1)  ; 1, 3
+)
+
+( (Color Yellow)
+  (Dendrite)
+  (  1.5  0.0  0.0  1.0)  ; Root
+  (  2.0  0.0  0.0  1.0)  ; R, 1
+  (
+    (  3.0  1.0  0.0  0.5)  ; R-1, 1
+    (Dot (Color White) (Name "Marker 3") (  3.0  1.0  0.0  0.5))
+    <(  3.1  1.0  0.0  0.2 S1)>
+    Normal
+  |
+    (  3.0 -1.0  0.0  0.5)  ; R-2, 1
+    (  4.0 -1.0  0.0  0.5)  ; R-2, 2
+    Incomplete
+  )  ;  End of split
+)  ;  End of tree
+"#;
+        let swc = SWC::parse_asc(content, Path::new("test.asc")).expect("fails to parse asc");
+        let nodes: Vec<_> = swc
+            .nodes()
+            .iter()
+            .map(|n| (n.id, n.strcture, n.pid))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (1, 1, -1),
+                (2, 1, 1),
+                (3, 1, 2),
+                (4, 3, 3),
+                (5, 3, 4),
+                (6, 3, 5),
+                (7, 3, 5),
+                (8, 3, 7),
+            ]
+        );
+        assert_eq!(swc.node(4).unwrap().radius, 0.5);
+    }
+
+    #[test]
+    fn unclosed_list() {
+        let content = "(\n  (Dendrite)\n  (1 2 3 4)\n";
+        let err = SWC::parse_asc(content, Path::new("test.asc"))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<SwcError>(),
+            Some(SwcError::Syntax {
+                line: 1,
+                column: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, path::PathBuf};
 
 #[derive(Debug)]
 pub struct RootNotFoundError;
@@ -10,3 +10,127 @@ impl fmt::Display for RootNotFoundError {
         write!(f, "Root not found")
     }
 }
+
+/// Diagnostic raised while reading a neuron file, lines and columns are 1-based.
+#[derive(Debug)]
+pub enum SwcError {
+    /// A cell can not be parsed as the type of its column.
+    InvalidField {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    /// The file is malformed, `column` counts characters.
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    /// A data line ends before all the mandatory columns.
+    MissingField {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+    /// The parent id of a node does not exist.
+    ParentNotFound {
+        path: PathBuf,
+        line: usize,
+        id: i32,
+        pid: i32,
+    },
+    /// A node id has already been used by a previous record.
+    DuplicateId {
+        path: PathBuf,
+        line: usize,
+        id: i32,
+        first_line: usize,
+    },
+    /// A node lists itself as parent.
+    SelfParent { path: PathBuf, line: usize, id: i32 },
+    /// Nodes whose parent chain loops back, listed from child to parent.
+    Cycle { path: PathBuf, ids: Vec<i32> },
+}
+
+/// Column names of a SWC record, in order, followed by those of ESWC.
+const SWC_COLUMNS: [&str; 12] = [
+    "id",
+    "type",
+    "x",
+    "y",
+    "z",
+    "radius",
+    "parent",
+    "seg_id",
+    "level",
+    "mode",
+    "timestamp",
+    "feature_value",
+];
+
+fn column_name(column: usize) -> &'static str {
+    SWC_COLUMNS.get(column.wrapping_sub(1)).unwrap_or(&"extra")
+}
+
+impl std::error::Error for SwcError {}
+
+impl fmt::Display for SwcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SwcError::InvalidField {
+                path,
+                line,
+                column,
+                reason,
+            } => write!(
+                f,
+                "{}:{line}: column {column} ({}): {reason}",
+                path.display(),
+                column_name(*column)
+            ),
+            SwcError::Syntax {
+                path,
+                line,
+                column,
+                reason,
+            } => write!(f, "{}:{line}:{column}: {reason}", path.display()),
+            SwcError::MissingField { path, line, column } => write!(
+                f,
+                "{}:{line}: column {column} ({}): missing field",
+                path.display(),
+                column_name(*column)
+            ),
+            SwcError::ParentNotFound {
+                path,
+                line,
+                id,
+                pid,
+            } => write!(
+                f,
+                "{}:{line}: column 7 (parent): parent not found, id: {id}, pid: {pid}",
+                path.display()
+            ),
+            SwcError::DuplicateId {
+                path,
+                line,
+                id,
+                first_line,
+            } => write!(
+                f,
+                "{}:{line}: column 1 (id): duplicate id {id}, first defined at line {first_line}",
+                path.display()
+            ),
+            SwcError::SelfParent { path, line, id } => write!(
+                f,
+                "{}:{line}: column 7 (parent): node {id} is its own parent",
+                path.display()
+            ),
+            SwcError::Cycle { path, ids } => {
+                let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "{}: cycle among ids: {}", path.display(), ids.join(", "))
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::{node::Node, swc::SWC, validate::Diagnostics, ParseMode};
+use std::{error::Error, path::Path};
+
+impl SWC {
+    /// Keep only nodes of the given structure types, see `filter`.
+    pub fn filter_structures(
+        &self,
+        include: &[i32],
+        exclude: &[i32],
+    ) -> Result<Self, Box<dyn Error>> {
+        let keep = |n: &Node| {
+            (include.is_empty() || include.contains(&n.strcture)) && !exclude.contains(&n.strcture)
+        };
+        if !self.nodes().iter().any(keep) {
+            let mut types: Vec<_> = self.nodes().iter().map(|n| n.strcture).collect();
+            types.sort_unstable();
+            types.dedup();
+            return Err(format!(
+                "no node left after filtering structure types {types:?}, include: {include:?}, exclude: {exclude:?}"
+            )
+            .into());
+        }
+        self.filter(keep)
+    }
+
+    /// Keep only nodes passing `keep`, cutting the trees where nodes are
+    /// removed. A kept node whose parent is removed starts a new tree from a
+    /// copy of the parent point, taking the structure and radius of the child,
+    /// so the segment joining them is still rendered, but nothing of the
+    /// removed part, e.g. the soma of a dendrite-only neuron. Fails if no node
+    /// is kept.
+    pub fn filter<F>(&self, keep: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: Fn(&Node) -> bool,
+    {
+        let nodes = self.nodes();
+        let mut next_id = nodes.iter().map(|n| n.id).max().unwrap_or(0) + 1;
+        let mut records = vec![];
+        for n in nodes.iter().filter(|n| keep(n)) {
+            let mut node = Node {
+                parent: None,
+                children: vec![],
+                ..n.clone()
+            };
+
+            match n.parent.map(|p| &nodes[p]) {
+                Some(p) if !keep(p) => {
+                    let mut anchor = Node::new(next_id, n.strcture, p.xyz(), n.radius, -1);
+                    anchor.extra = n.extra.clone();
+                    records.push((0, anchor));
+                    node.pid = next_id;
+                    next_id += 1;
+                }
+                Some(p) => node.pid = p.id,
+                None => node.pid = -1,
+            }
+            records.push((0, node));
+        }
+
+        if records.is_empty() {
+            return Err("no node left after filtering".into());
+        }
+
+        let mut diagnostics = Diagnostics::new(ParseMode::Strict);
+        let mut swc = Self::build(records, Path::new(""), &mut diagnostics)?;
+        swc.comments = self.comments.clone();
+        swc.metadata = self.metadata.clone();
+        Ok(swc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_structures() {
+        let content = "1 1 0 0 0 5 -1\n2 3 0 10 0 1 1\n3 3 0 20 0 1 2\n4 2 0 -10 0 0.5 1\n5 2 0 -20 0 0.5 4\n";
+        let (swc, _) = SWC::parse(content.as_bytes(), Path::new("test.swc"), ParseMode::Strict)
+            .expect("fails to parse swc");
+
+        let dendrite = swc.filter_structures(&[3], &[]).expect("fails to filter");
+        let nodes: Vec<_> = dendrite
+            .nodes()
+            .iter()
+            .map(|n| (n.id, n.strcture, n.y, n.radius, n.parent))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (6, 3, 0.0, 1.0, None),
+                (2, 3, 10.0, 1.0, Some(0)),
+                (3, 3, 20.0, 1.0, Some(1)),
+            ]
+        );
+
+        let no_axon = swc.filter_structures(&[], &[2]).expect("fails to filter");
+        assert_eq!(no_axon.len(), 3);
+        assert_eq!(no_axon.roots(), [0]);
+
+        let err = swc.filter_structures(&[4], &[]).err().expect("should fail");
+        assert_eq!(
+            err.to_string(),
+            "no node left after filtering structure types [1, 2, 3], include: [4], exclude: []"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::vec::Vec3f;
+use std::fmt;
+
+/// Header fields of a SWC, parsed from comment lines like `# SCALE 1 1 1`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    fields: Vec<(String, String)>, // (key, value), in file order
+}
+
+impl Metadata {
+    /// Collect `KEY value` pairs, keys are upper case words such as
+    /// `ORIGINAL_SOURCE` or `FIELD/LAYER`, optionally followed by `:`.
+    /// Other comments are free text and skipped.
+    pub fn parse<S: AsRef<str>>(comments: &[S]) -> Metadata {
+        let fields = comments
+            .iter()
+            .filter_map(|comment| field(comment.as_ref()))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Metadata { fields }
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Value of the first field named `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.fields.retain(|(k, _)| k != key);
+    }
+
+    /// Per-axis `SCALE` applied to coordinates, a single value for all axes.
+    pub fn scale(&self) -> Option<Vec3f> {
+        self.get("SCALE").and_then(parse_vec3f)
+    }
+
+    /// Per-axis `SHIFT` applied to coordinates, after `SCALE`.
+    pub fn shift(&self) -> Option<Vec3f> {
+        self.get("SHIFT").and_then(parse_vec3f)
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{key}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a comment into a `(key, value)` field, `None` for free text.
+pub fn field(comment: &str) -> Option<(&str, &str)> {
+    let comment = comment.trim();
+    let (key, value) = match comment.split_once(char::is_whitespace) {
+        Some((key, value)) => (key, value.trim()),
+        None => (comment, ""),
+    };
+    let key = key.strip_suffix(':').unwrap_or(key);
+    is_key(key).then_some((key, value))
+}
+
+fn is_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_uppercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_' || c == '/')
+}
+
+fn parse_vec3f(value: &str) -> Option<Vec3f> {
+    let v = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|a| !a.is_empty())
+        .map(|a| a.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match v[..] {
+        [a] => Some(Vec3f::new(a, a, a)),
+        [x, y, z] => Some(Vec3f::new(x, y, z)),
+        _ => None,
+    }
+}
@@ -0,0 +1,350 @@
+use super::{
+    error_kind::SwcError,
+    node::{Node, APICAL, AXON, DENDRITE, SOMA, UNDEFINED},
+    swc::SWC,
+    validate::Diagnostics,
+    ParseMode,
+};
+use crate::vec::Vec3f;
+use roxmltree::{Document, Node as XmlNode};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// See also: https://docs.neuroml.org/Userdocs/Schemas/Morphologies.html
+
+struct Segment {
+    id: i32,
+    line: usize,
+    parent: Option<(i32, f32)>, // (segment, fraction along)
+    proximal: Option<(Vec3f, f32)>,
+    distal: (Vec3f, f32),
+}
+
+impl SWC {
+    /// Read the first `<morphology>` of a NeuroML2 file. Every segment ends
+    /// with a node at its distal point, and starts from an extra node when its
+    /// proximal point differs from where it is attached, so the proximal
+    /// diameter is kept. Segments attached part-way along their parent, with
+    /// a `fractionAlong` other than 0 or 1, are moved to its nearest end.
+    pub fn read_neuroml(fname: &str) -> Result<Self, Box<dyn Error>> {
+        let fname = fs::canonicalize(PathBuf::from(fname))?;
+        let content = fs::read_to_string(&fname)?;
+        Self::parse_neuroml(&content, &fname)
+    }
+
+    /// Parse NeuroML2 from a string, `path` is only used to locate diagnostics.
+    pub fn parse_neuroml(content: &str, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let syntax = |line: usize, column: usize, reason: String| SwcError::Syntax {
+            path: path.to_path_buf(),
+            line,
+            column,
+            reason,
+        };
+
+        let doc = Document::parse(content).map_err(|err| {
+            let pos = err.pos();
+            syntax(pos.row as usize, pos.col as usize, err.to_string())
+        })?;
+        let morphology = doc
+            .descendants()
+            .find(|n| n.has_tag_name("morphology"))
+            .ok_or_else(|| syntax(1, 1, "morphology not found".to_string()))?;
+
+        let mut segments = vec![];
+        for n in morphology.children().filter(|n| n.has_tag_name("segment")) {
+            let pos = doc.text_pos_at(n.range().start);
+            segments.push(
+                parse_segment(n, pos.row as usize)
+                    .map_err(|reason| syntax(pos.row as usize, pos.col as usize, reason))?,
+            );
+        }
+
+        let structures = structures(morphology);
+        let index: HashMap<i32, usize> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id, i))
+            .collect();
+
+        // reported by segment ids, before they are renumbered as nodes
+        for s in segments.iter() {
+            match s.parent {
+                Some((p, _)) if !index.contains_key(&p) => {
+                    return Err(Box::new(SwcError::ParentNotFound {
+                        path: path.to_path_buf(),
+                        line: s.line,
+                        id: s.id,
+                        pid: p,
+                    }));
+                }
+                _ => (),
+            }
+        }
+
+        // where a segment attaches to its parent, the nearest end of it
+        let attach = |s: &Segment| -> Option<(usize, bool)> {
+            let (p, fraction) = s.parent?;
+            index.get(&p).map(|&j| (j, fraction >= 0.5))
+        };
+        let attach_point = |s: &Segment| -> Option<(Vec3f, f32)> {
+            let (j, distal) = attach(s)?;
+            match distal {
+                true => Some(segments[j].distal),
+                false => segments[j].proximal.or(Some(segments[j].distal)),
+            }
+        };
+
+        // a proximal node is only needed when it differs from the attach point
+        let mut ids = vec![]; // (proximal, distal) node ids of each segment
+        let mut next_id = 1..;
+        for s in segments.iter() {
+            let proximal = s.proximal.is_some() && s.proximal != attach_point(s);
+            let proximal_id = proximal.then(|| next_id.next().unwrap());
+            ids.push((proximal_id, next_id.next().unwrap()));
+        }
+
+        // first node of a segment, following parents which also start from
+        // their proximal end, bounded in case of cycles
+        let start = |mut j: usize| -> i32 {
+            for _ in 0..segments.len() {
+                if let Some(id) = ids[j].0 {
+                    return id;
+                }
+                match attach(&segments[j]) {
+                    Some((p, true)) => return ids[p].1,
+                    Some((p, false)) => j = p,
+                    None => break,
+                }
+            }
+            ids[j].1
+        };
+
+        let mut records = vec![];
+        for (i, s) in segments.iter().enumerate() {
+            let attach = match attach(s) {
+                Some((j, true)) => ids[j].1,
+                Some((j, false)) => start(j),
+                None => -1,
+            };
+
+            let structure = structures.get(&s.id).copied().unwrap_or(UNDEFINED);
+            let (proximal_id, distal_id) = ids[i];
+            let pid = match (proximal_id, s.proximal) {
+                (Some(id), Some((xyz, d))) => {
+                    records.push((s.line, Node::new(id, structure, xyz, d / 2.0, attach)));
+                    id
+                }
+                _ => attach,
+            };
+            let (xyz, d) = s.distal;
+            records.push((s.line, Node::new(distal_id, structure, xyz, d / 2.0, pid)));
+        }
+
+        let mut diagnostics = Diagnostics::new(ParseMode::Strict);
+        Self::build(records, path, &mut diagnostics)
+    }
+}
+
+fn parse_segment(n: XmlNode, line: usize) -> Result<Segment, String> {
+    let child = |name: &str| n.children().find(|c| c.has_tag_name(name));
+    let parent = match child("parent") {
+        Some(p) => Some((
+            attribute(p, "segment")?,
+            p.attribute("fractionAlong")
+                .map_or(Ok(1.0), |_| attribute(p, "fractionAlong"))?,
+        )),
+        None => None,
+    };
+    let proximal = child("proximal").map(point).transpose()?;
+    let distal = point(child("distal").ok_or("missing distal point")?)?;
+    Ok(Segment {
+        id: attribute(n, "id")?,
+        line,
+        parent,
+        proximal,
+        distal,
+    })
+}
+
+fn point(n: XmlNode) -> Result<(Vec3f, f32), String> {
+    let xyz = Vec3f::new(attribute(n, "x")?, attribute(n, "y")?, attribute(n, "z")?);
+    Ok((xyz, attribute(n, "diameter")?))
+}
+
+fn attribute<T: std::str::FromStr>(n: XmlNode, name: &str) -> Result<T, String> {
+    let value = n
+        .attribute(name)
+        .ok_or_else(|| format!("missing attribute `{name}` in <{}>", n.tag_name().name()))?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid attribute `{name}`: `{value}`"))
+}
+
+/// Structure type of segments, from segment groups named like `soma_group`,
+/// `somatic`, `axonal`, `basal` or `apic`, or with the NeuroLex id of soma,
+/// axon or dendrite. A segment in several
+/// groups takes the first of soma, axon, apical dendrite and dendrite.
+fn structures(morphology: XmlNode) -> HashMap<i32, i32> {
+    let groups: HashMap<&str, XmlNode> = morphology
+        .children()
+        .filter(|n| n.has_tag_name("segmentGroup"))
+        .filter_map(|n| Some((n.attribute("id")?, n)))
+        .collect();
+
+    let mut structures = HashMap::new();
+    for structure in [DENDRITE, APICAL, AXON, SOMA] {
+        for (&id, &group) in groups.iter() {
+            if classify(id, group.attribute("neuroLexId")) != Some(structure) {
+                continue;
+            }
+
+            // members of the group and all included groups
+            let mut visited = HashSet::from([id]);
+            let mut stack = vec![group];
+            while let Some(g) = stack.pop() {
+                for c in g.children() {
+                    if c.has_tag_name("member") {
+                        if let Some(s) = c.attribute("segment").and_then(|s| s.parse().ok()) {
+                            structures.insert(s, structure);
+                        }
+                    } else if c.has_tag_name("include") {
+                        let include = c.attribute("segmentGroup").unwrap_or_default();
+                        if let Some(&g) = groups.get(include).filter(|_| visited.insert(include)) {
+                            stack.push(g);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    structures
+}
+
+fn classify(id: &str, neurolex_id: Option<&str>) -> Option<i32> {
+    match neurolex_id {
+        Some("sao1044911821") => return Some(SOMA),
+        Some("sao864921383") => return Some(AXON),
+        Some("sao1211023249") => return Some(DENDRITE),
+        _ => (),
+    }
+
+    let id = id.to_lowercase();
+    if id.contains("soma") {
+        Some(SOMA)
+    } else if id.contains("axon") {
+        Some(AXON)
+    } else if id.contains("apic") {
+        Some(APICAL)
+    } else if id.contains("dend") || id.contains("basal") {
+        Some(DENDRITE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_neuroml() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<neuroml xmlns="http://www.neuroml.org/schema/neuroml2" id="test">
+  <cell id="cell">
+    <morphology id="morphology">
+      <segment id="0" name="soma">
+        <proximal x="0" y="0" z="0" diameter="10"/>
+        <distal x="0" y="10" z="0" diameter="10"/>
+      </segment>
+      <segment id="1" name="dend">
+        <parent segment="0"/>
+        <proximal x="0" y="10" z="0" diameter="2"/>
+        <distal x="0" y="20" z="0" diameter="1"/>
+      </segment>
+      <segment id="2" name="dend2">
+        <parent segment="1"/>
+        <distal x="0" y="30" z="0" diameter="1"/>
+      </segment>
+      <segment id="3" name="axon">
+        <parent segment="0" fractionAlong="0"/>
+        <proximal x="0" y="0" z="0" diameter="10"/>
+        <distal x="0" y="-10" z="0" diameter="1"/>
+      </segment>
+      <segmentGroup id="soma_group" neuroLexId="sao1044911821">
+        <member segment="0"/>
+      </segmentGroup>
+      <segmentGroup id="dendrite_group">
+        <include segmentGroup="apical"/>
+        <member segment="1"/>
+      </segmentGroup>
+      <segmentGroup id="apical">
+        <member segment="2"/>
+      </segmentGroup>
+      <segmentGroup id="axon_group">
+        <member segment="3"/>
+      </segmentGroup>
+    </morphology>
+  </cell>
+</neuroml>
+"#;
+        let swc = SWC::parse_neuroml(content, Path::new("test.nml")).expect("fails to parse");
+        let nodes: Vec<_> = swc
+            .nodes()
+            .iter()
+            .map(|n| (n.id, n.strcture, n.radius, n.pid))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (1, 1, 5.0, -1),
+                (2, 1, 5.0, 1),
+                (3, 3, 1.0, 2),
+                (4, 3, 0.5, 3),
+                (5, 4, 0.5, 4),
+                (6, 2, 0.5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn neuron_group_names() {
+        let content = r#"<neuroml><morphology id="m">
+  <segment id="0"><proximal x="0" y="0" z="0" diameter="1"/><distal x="0" y="1" z="0" diameter="1"/></segment>
+  <segment id="1"><parent segment="0"/><distal x="0" y="2" z="0" diameter="1"/></segment>
+  <segment id="2"><parent segment="0"/><distal x="1" y="1" z="0" diameter="1"/></segment>
+  <segment id="3"><parent segment="0"/><distal x="-1" y="1" z="0" diameter="1"/></segment>
+  <segmentGroup id="somatic"><member segment="0"/></segmentGroup>
+  <segmentGroup id="axonal"><member segment="1"/></segmentGroup>
+  <segmentGroup id="basal"><member segment="2"/></segmentGroup>
+  <segmentGroup id="apic"><member segment="3"/></segmentGroup>
+</morphology></neuroml>"#;
+        let swc = SWC::parse_neuroml(content, Path::new("test.nml")).expect("fails to parse");
+        let types: Vec<_> = swc.nodes().iter().map(|n| n.strcture).collect();
+        assert_eq!(types, [1, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn dangling_parent() {
+        let content = r#"<neuroml><morphology id="m">
+  <segment id="0"><proximal x="0" y="0" z="0" diameter="1"/><distal x="0" y="1" z="0" diameter="1"/></segment>
+  <segment id="1"><parent segment="7"/><distal x="0" y="2" z="0" diameter="1"/></segment>
+</morphology></neuroml>"#;
+        let err = SWC::parse_neuroml(content, Path::new("test.nml"))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<SwcError>(),
+            Some(SwcError::ParentNotFound {
+                line: 3,
+                id: 1,
+                pid: 7,
+                ..
+            })
+        ));
+    }
+}
@@ -1,16 +1,14 @@
-use crate::{
-    render::{Object, SDFObject, VAxisLinearGradient},
-    sdf::RoundCone,
-    vec::Vec3f,
-};
-use std::{
-    cell::RefCell,
-    collections::HashSet,
-    fmt,
-    rc::{Rc, Weak},
-    sync::Arc,
-};
+use crate::vec::Vec3f;
+use std::fmt;
 
+// Structure types of the SWC standard, higher ones are specific to tools.
+pub const UNDEFINED: i32 = 0;
+pub const SOMA: i32 = 1;
+pub const AXON: i32 = 2;
+pub const DENDRITE: i32 = 3; // basal
+pub const APICAL: i32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Node {
     pub id: i32,
     pub strcture: i32,
@@ -19,33 +17,49 @@ pub struct Node {
     pub z: f32,
     pub radius: f32,
     pub pid: i32,
+    pub extra: Vec<String>, // extended columns after the 7th, e.g. those of ESWC, as read
 
-    pub parent: Weak<RefCell<Node>>,
-    pub children: Vec<Rc<RefCell<Node>>>,
+    pub parent: Option<usize>, // index in `SWC`, `None` for roots
+    pub children: Vec<usize>,
 }
 
 impl Node {
-    pub fn xyz(&self) -> Vec3f {
-        Vec3f::new(self.x, self.y, self.z)
+    pub fn new(id: i32, strcture: i32, xyz: Vec3f, radius: f32, pid: i32) -> Node {
+        Node {
+            id,
+            strcture,
+            x: xyz.x,
+            y: xyz.y,
+            z: xyz.z,
+            radius,
+            pid,
+            extra: Vec::new(),
+
+            parent: None,
+            children: Vec::new(),
+        }
     }
 
-    pub fn push_child(&mut self, child: Rc<RefCell<Node>>) {
-        self.children.push(child)
+    pub fn xyz(&self) -> Vec3f {
+        Vec3f::new(self.x, self.y, self.z)
     }
 
-    pub fn parent(&self) -> Option<Rc<RefCell<Node>>> {
-        self.parent.upgrade()
+    /// Value of an extended column, 1-based as in the file, so the ESWC
+    /// `seg_id`, `level`, `mode`, `timestamp` and `feature_value` are 8 to 12.
+    /// `None` if missing or not a number.
+    pub fn extra(&self, column: usize) -> Option<f32> {
+        self.extra.get(column.checked_sub(8)?)?.parse().ok()
     }
 
     pub fn _is_termination(&self) -> bool {
-        self.children.len() == 0
+        self.children.is_empty()
     }
 
     pub fn _is_elongation(&self) -> bool {
         self.children.len() == 1
     }
 
-    pub fn _is_bifurcation(&self) -> bool {
+    pub fn is_bifurcation(&self) -> bool {
         self.children.len() > 1
     }
 
@@ -55,57 +69,6 @@ impl Node {
             self.id, self.strcture, self.x, self.y, self.z, self.radius, self.pid
         )
     }
-
-    pub fn sdf_with_path_decay<F>(&self, decay_fn: F) -> Vec<Arc<dyn Object>>
-    where
-        F: Fn(f32) -> Vec3f,
-    {
-        let mut visited = HashSet::new();
-        visited.insert(self.id);
-        let mut out = vec![];
-        Self::sdf_with_path_decay_impl(&self, &decay_fn, 0.0, &mut visited, &mut out);
-        out
-    }
-
-    fn sdf_with_path_decay_impl<F>(
-        &self,
-        decay_fn: &F,
-        cur: f32,
-        visited: &mut HashSet<i32>,
-        out: &mut Vec<Arc<dyn Object>>,
-    ) where
-        F: Fn(f32) -> Vec3f,
-    {
-        let mut ns = vec![];
-        if let Some(p) = self.parent() {
-            if visited.insert(p.borrow().id) {
-                ns.push(p);
-            }
-        }
-        for c in self.children.iter() {
-            if visited.insert(c.borrow().id) {
-                ns.push(c.clone());
-            }
-        }
-
-        let a = decay_fn(cur);
-        let a_norm = a.norm();
-        for n in ns {
-            let n = n.borrow();
-            let acc = cur + (self.xyz() - n.xyz()).norm();
-            let b = decay_fn(acc);
-
-            const EPS: f32 = 1e-6;
-            if a_norm > EPS && b.norm() > EPS {
-                let m = VAxisLinearGradient::new(a, b);
-                let link = Box::new(RoundCone::new(self.xyz(), self.radius, n.xyz(), n.radius)); // TODO: perf
-                let obj = SDFObject::new(link, m);
-                out.push(obj);
-            }
-
-            Self::sdf_with_path_decay_impl(&n, decay_fn, acc, visited, out);
-        }
-    }
 }
 
 impl fmt::Display for Node {
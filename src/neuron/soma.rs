@@ -0,0 +1,233 @@
+use super::{
+    node::{Node, SOMA},
+    swc::SWC,
+};
+use crate::{
+    sdf::{Ellipsoid, Sphere, SDF},
+    vec::Vec3f,
+};
+
+/// Soma described by several connected soma nodes, rendered as a body rather
+/// than as links between its nodes.
+pub struct Soma {
+    pub nodes: Vec<usize>, // indices of the soma nodes
+    pub shape: SomaShape,
+}
+
+pub enum SomaShape {
+    /// NeuroMorpho three-point soma, a center with two nodes one radius away.
+    ThreePoint { center: Vec3f, radius: f32 },
+    /// Outline of the soma, e.g. traced in Neurolucida, fitted by an ellipsoid.
+    Contour {
+        center: Vec3f,
+        axes: [Vec3f; 3],
+        radii: Vec3f,
+    },
+}
+
+impl Soma {
+    pub fn sdf(&self) -> Box<dyn SDF> {
+        match self.shape {
+            SomaShape::ThreePoint { center, radius } => Box::new(Sphere::new(center, radius)),
+            SomaShape::Contour {
+                center,
+                axes,
+                radii,
+            } => Box::new(Ellipsoid::new(center, axes, radii)),
+        }
+    }
+}
+
+impl SWC {
+    /// Somas made of connected soma nodes, following either the three-point
+    /// convention or describing a contour. Single node somas and stacks of
+    /// soma cylinders are left to be rendered as any other node.
+    pub fn somas(&self) -> Vec<Soma> {
+        let nodes = self.nodes();
+        let mut somas = vec![];
+        for (i, n) in nodes.iter().enumerate() {
+            let is_head = n.strcture == SOMA && n.parent.is_none_or(|p| nodes[p].strcture != SOMA);
+            if !is_head {
+                continue;
+            }
+
+            // soma nodes connected to the head
+            let mut component = vec![];
+            let mut stack = vec![i];
+            while let Some(j) = stack.pop() {
+                component.push(j);
+                let children = nodes[j].children.iter();
+                stack.extend(children.filter(|&&c| nodes[c].strcture == SOMA));
+            }
+
+            let points: Vec<&Node> = component.iter().map(|&j| &nodes[j]).collect();
+            let shape = three_point(&points).or_else(|| contour(&points));
+            if let Some(shape) = shape {
+                somas.push(Soma {
+                    nodes: component,
+                    shape,
+                });
+            }
+        }
+        somas
+    }
+}
+
+fn three_point(points: &[&Node]) -> Option<SomaShape> {
+    let [c, a, b] = points else {
+        return None;
+    };
+
+    const TOL: f32 = 0.1; // relative to the radius
+    let r = c.radius;
+    let close = |x: f32, y: f32| f32::abs(x - y) <= TOL * r;
+    let (ca, cb) = (a.xyz() - c.xyz(), b.xyz() - c.xyz());
+    let ok = r > 0.0
+        && close(a.radius, r)
+        && close(b.radius, r)
+        && close(ca.norm(), r)
+        && close(cb.norm(), r)
+        && (ca + cb).norm() <= TOL * r;
+    ok.then_some(SomaShape::ThreePoint {
+        center: c.xyz(),
+        radius: r,
+    })
+}
+
+fn contour(points: &[&Node]) -> Option<SomaShape> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    let n = points.len() as f32;
+    let center = points
+        .iter()
+        .fold(Vec3f::new(0.0, 0.0, 0.0), |acc, p| acc + p.xyz())
+        / n;
+    let mut cov = [[0.0; 3]; 3];
+    for p in points.iter() {
+        let d = (p.xyz() - center).tuple();
+        let d = [d.0, d.1, d.2];
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[i][j] += d[i] * d[j] / n;
+            }
+        }
+    }
+
+    // points of a contour outline the soma, while those of a stack of
+    // cylinders lie on its axis with radii describing the body
+    let rms = f32::sqrt(cov[0][0] + cov[1][1] + cov[2][2]);
+    let mean_radius = points.iter().map(|p| p.radius).sum::<f32>() / n;
+    if mean_radius >= 0.5 * rms {
+        return None;
+    }
+
+    // points spread on an ellipse have a variance of a^2/2 along each axis,
+    // the normal of the contour takes the spread of stacked contours if any
+    let (values, axes) = eigen(cov);
+    let a = f32::sqrt(2.0 * values[0]);
+    let b = f32::sqrt(2.0 * values[1]);
+    let c = f32::max(f32::sqrt(3.0 * values[2]), b);
+    let radii = Vec3f::new(a, b, c) + mean_radius;
+    Some(SomaShape::Contour {
+        center,
+        axes,
+        radii,
+    })
+}
+
+/// Eigen decomposition of a symmetric matrix by Jacobi rotations, eigenvalues
+/// in descending order with their unit eigenvectors.
+fn eigen(mut a: [[f32; 3]; 3]) -> ([f32; 3], [Vec3f; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..32 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .into_iter()
+            .max_by(|&(i, j), &(k, l)| a[i][j].abs().total_cmp(&a[k][l].abs()))
+            .unwrap();
+        if a[p][q].abs() < 1e-12 {
+            break;
+        }
+
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + f32::sqrt(theta * theta + 1.0));
+        let c = 1.0 / f32::sqrt(t * t + 1.0);
+        let s = t * c;
+        for row in a.iter_mut().chain(v.iter_mut()) {
+            let (kp, kq) = (row[p], row[q]);
+            row[p] = c * kp - s * kq;
+            row[q] = s * kp + c * kq;
+        }
+        let (rp, rq) = (a[p], a[q]);
+        a[p] = [0, 1, 2].map(|k| c * rp[k] - s * rq[k]);
+        a[q] = [0, 1, 2].map(|k| s * rp[k] + c * rq[k]);
+    }
+
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| a[j][j].total_cmp(&a[i][i]));
+    let values = order.map(|i| a[i][i].max(0.0));
+    let axes = order.map(|i| {
+        let axis = Vec3f::new(v[0][i], v[1][i], v[2][i]);
+        axis / axis.norm()
+    });
+    (values, axes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neuron::ParseMode;
+    use std::path::Path;
+
+    fn parse(content: &str) -> SWC {
+        let path = Path::new("test.swc");
+        SWC::parse(content.as_bytes(), path, ParseMode::Strict)
+            .expect("fails to parse swc")
+            .0
+    }
+
+    #[test]
+    fn three_point_soma() {
+        let swc = parse("1 1 0 0 0 5 -1\n2 1 0 -5 0 5 1\n3 1 0 5 0 5 1\n4 3 0 0 10 1 1\n");
+        let somas = swc.somas();
+        assert!(matches!(
+            somas[..],
+            [Soma {
+                shape: SomaShape::ThreePoint { radius: 5.0, .. },
+                ..
+            }]
+        ));
+        assert_eq!(somas[0].nodes, [0, 2, 1]);
+    }
+
+    #[test]
+    fn contour_soma() {
+        let content: String = (0..8)
+            .map(|i| {
+                let t = i as f32 * std::f32::consts::PI / 4.0;
+                let pid = if i == 0 { -1 } else { i };
+                format!(
+                    "{} 1 {} {} 3 0 {pid}\n",
+                    i + 1,
+                    10.0 * t.cos(),
+                    5.0 * t.sin()
+                )
+            })
+            .collect();
+        let swc = parse(&(content + "9 3 10 0 3 1 1\n"));
+        let somas = swc.somas();
+        let SomaShape::Contour { center, radii, .. } = somas[0].shape else {
+            panic!("contour not detected");
+        };
+        assert!((center - Vec3f::new(0.0, 0.0, 3.0)).norm() < 1e-4);
+        assert!((radii - Vec3f::new(10.0, 5.0, 5.0)).norm() < 1e-3);
+        assert!(somas[0].sdf().signed_distance(Vec3f::new(0.0, 0.0, 7.9)) < 0.0);
+    }
+
+    #[test]
+    fn single_node_soma() {
+        let swc = parse("1 1 0 0 0 5 -1\n2 3 0 0 10 1 1\n");
+        assert!(swc.somas().is_empty());
+    }
+}
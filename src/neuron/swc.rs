@@ -1,82 +1,216 @@
-use super::{error_kind::RootNotFoundError, node::Node};
+use super::{
+    error_kind::{RootNotFoundError, SwcError},
+    metadata::{self, Metadata},
+    node::{Node, SOMA, UNDEFINED},
+    traverse::{Bfs, PostOrder, PreOrder},
+    validate::{self, Diagnostics},
+};
 use crate::{
-    render::{Material, Object, SDFObject, SolidColor},
+    render::{Label, Material, Object, SDFObject, SolidColor, VAxisLinearGradient},
     sdf::{RoundCone, Sphere, SDF},
     vec::Vec3f,
 };
 use std::{
-    cell::RefCell,
     collections::HashMap,
     error::Error,
     fmt, fs,
     fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-    rc::{Rc, Weak},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 
+/// Neuron tree stored in an arena, nodes refer to each other by index.
 pub struct SWC {
-    pub root: Rc<RefCell<Node>>,
-    pub count: usize,
+    nodes: Vec<Node>,
+    index: HashMap<i32, usize>,       // id -> index
+    roots: Vec<usize>,                // every node without parent, in file order
+    pub(super) comments: Vec<String>, // comment lines, without the leading `#`
+    pub(super) metadata: Metadata,
 }
 
-const ROOT_ID: i32 = 1;
-
 impl SWC {
     pub fn read(fname: &str) -> Result<Self, Box<dyn Error>> {
-        let fname = fs::canonicalize(&PathBuf::from(fname))?;
-        let file = File::open(fname)?;
-        let mut nodes = HashMap::<i32, Rc<RefCell<Node>>>::new();
-        for line in BufReader::new(file).lines() {
+        let (swc, _) = Self::read_with_mode(fname, ParseMode::Strict)?;
+        Ok(swc)
+    }
+
+    pub fn read_with_mode(
+        fname: &str,
+        mode: ParseMode,
+    ) -> Result<(Self, Vec<SwcError>), Box<dyn Error>> {
+        let fname = fs::canonicalize(PathBuf::from(fname))?;
+        let file = File::open(&fname)?;
+        Self::parse(BufReader::new(file), &fname, mode)
+    }
+
+    /// Parse SWC from a reader, `path` is only used to locate diagnostics.
+    pub fn parse<R: BufRead>(
+        reader: R,
+        path: &Path,
+        mode: ParseMode,
+    ) -> Result<(Self, Vec<SwcError>), Box<dyn Error>> {
+        let mut diagnostics = Diagnostics::new(mode);
+
+        // collect all records first, so parents may appear after their children
+        let mut records = Vec::new();
+        let mut comments = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
             let line = line?;
-            if line.is_empty() || line.starts_with("#") {
-                continue; // skip comment line
+            if let Some(comment) = line.trim_start().strip_prefix('#') {
+                comments.push(comment.trim_end().to_string());
+                continue;
             }
 
-            let cells: Vec<&str> = line.split(" ").collect();
-            if let [id, structure, x, y, z, radius, pid, ..] = cells[..] {
-                let id: i32 = id.parse()?;
-                let pid: i32 = pid.parse()?;
-                let parent = match pid {
-                    -1 => None,
-                    _ => nodes.get_mut(&pid).or_else(|| {
-                        println!("parent not found, id: {id}, pid: {pid}");
-                        None
-                    }),
-                };
+            let cells = tokenize(&line);
+            if cells.is_empty() {
+                continue; // skip blank line
+            }
 
-                let node = Rc::new(RefCell::new(Node {
-                    id,
-                    strcture: structure.parse()?,
-                    x: x.parse()?,
-                    y: y.parse()?,
-                    z: z.parse()?,
-                    radius: radius.parse()?,
-                    pid,
-
-                    parent: match &parent {
-                        Some(p) => Rc::downgrade(&p),
-                        None => Weak::new(),
-                    },
-                    children: Vec::new(),
-                }));
-
-                if let Some(p) = parent {
-                    (*p.borrow_mut()).push_child(Rc::clone(&node));
-                }
-                nodes.insert(id, node);
+            match parse_record(&cells, path, i + 1) {
+                Ok(node) => records.push((i + 1, node)),
+                Err(err) => diagnostics.report(err)?,
             };
         }
 
+        let mut swc = Self::build(records, path, &mut diagnostics)?;
+        swc.metadata = Metadata::parse(&comments);
+        swc.comments = comments;
+        Ok((swc, diagnostics.errors))
+    }
+
+    /// Validate and link `(line, node)` records by their parent ids.
+    pub(super) fn build(
+        records: Vec<(usize, Node)>,
+        path: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Self, Box<dyn Error>> {
+        let records = validate::dedup(records, path, diagnostics)?;
+        let parents = validate::resolve_parents(&records, path, diagnostics)?;
+        let mut nodes: Vec<_> = records.into_iter().map(|(_, node)| node).collect();
+
+        let mut roots = Vec::new();
+        for (i, parent) in parents.into_iter().enumerate() {
+            nodes[i].parent = parent;
+            match parent {
+                Some(p) => nodes[p].children.push(i),
+                None => roots.push(i),
+            }
+        }
+
+        if roots.is_empty() {
+            return Err(Box::new(RootNotFoundError));
+        }
+
+        let index = nodes.iter().enumerate().map(|(i, n)| (n.id, i)).collect();
         Ok(SWC {
-            root: nodes.remove(&ROOT_ID).ok_or(Box::new(RootNotFoundError))?,
-            count: nodes.len(),
+            nodes,
+            index,
+            roots,
+            comments: Vec::new(),
+            metadata: Metadata::default(),
         })
     }
 
-    pub fn node(&self, id: i32) -> Option<Rc<RefCell<Node>>> {
-        Self::node_impl(self.root.clone(), id)
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Apply the `SCALE` then `SHIFT` of the header to coordinates, radii are
+    /// left untouched. Both fields are consumed, and their comment lines
+    /// dropped, so applying twice or reading back a written SWC is a no-op.
+    pub fn apply_scale_shift(&mut self) {
+        let scale = self.metadata.scale().unwrap_or(Vec3f::new(1.0, 1.0, 1.0));
+        let shift = self.metadata.shift().unwrap_or(Vec3f::new(0.0, 0.0, 0.0));
+        self.map_geometry(|n| (n.xyz() * scale + shift, n.radius));
+        self.metadata.remove("SCALE");
+        self.metadata.remove("SHIFT");
+        self.comments
+            .retain(|c| !matches!(metadata::field(c), Some(("SCALE" | "SHIFT", _))));
+    }
+
+    pub fn write(&self, fname: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(fname)?;
+        self.write_to(BufWriter::new(file))?;
+        Ok(())
+    }
+
+    /// Serialize comments then nodes in their original order. Parents are
+    /// written as resolved, so nodes rooted in lenient mode get `-1`.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for comment in self.comments.iter() {
+            writeln!(w, "#{comment}")?;
+        }
+
+        for n in self.nodes.iter() {
+            let pid = n.parent.map_or(-1, |p| self.nodes[p].id);
+            write!(
+                w,
+                "{} {} {} {} {} {} {}",
+                n.id, n.strcture, n.x, n.y, n.z, n.radius, pid
+            )?;
+            for v in n.extra.iter() {
+                write!(w, " {v}")?;
+            }
+            writeln!(w)?;
+        }
+        w.flush()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Move and resize nodes in place, `f` returns the new position and
+    /// radius of a node. Ids and links are left untouched.
+    pub fn map_geometry<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Node) -> (Vec3f, f32),
+    {
+        for n in self.nodes.iter_mut() {
+            let (xyz, radius) = f(n);
+            (n.x, n.y, n.z) = xyz.tuple();
+            n.radius = radius;
+        }
+    }
+
+    /// Index of the first node of every tree, in file order.
+    #[allow(dead_code)] // API
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn index_of(&self, id: i32) -> Option<usize> {
+        self.index.get(&id).copied()
+    }
+
+    #[allow(dead_code)] // API
+    pub fn node(&self, id: i32) -> Option<&Node> {
+        self.index_of(id).map(|i| &self.nodes[i])
+    }
+
+    #[allow(dead_code)] // API
+    pub fn node_mut(&mut self, id: i32) -> Option<&mut Node> {
+        self.index_of(id).map(|i| &mut self.nodes[i])
+    }
+
+    pub fn preorder(&self, start: usize) -> PreOrder<'_> {
+        PreOrder::new(&self.nodes, start)
+    }
+
+    #[allow(dead_code)] // API
+    pub fn postorder(&self, start: usize) -> PostOrder<'_> {
+        PostOrder::new(&self.nodes, start)
+    }
+
+    #[allow(dead_code)] // API
+    pub fn bfs(&self, start: usize) -> Bfs<'_> {
+        Bfs::new(&self.nodes, start)
     }
 
     pub fn sdf(&self) -> Vec<Arc<dyn Object>> {
@@ -84,13 +218,153 @@ impl SWC {
     }
 
     pub fn sdf_with_material(&self, material: Arc<dyn Material>) -> Vec<Arc<dyn Object>> {
-        let mut out = vec![];
-        let n = &self.root.borrow();
-        Self::sdf_with_material_impl(n, material.clone(), &mut out);
-        if out.len() == 0 {
-            let sphere = Sphere::new(n.xyz(), n.radius);
-            let obj = SDFObject::new(Box::from(sphere), material);
-            out.push(obj);
+        self.sdf_with(|_| material.clone(), |_, c| structure_label(c).into())
+    }
+
+    /// Render with the intensity of an extended column (1-based, e.g. 12 for
+    /// the ESWC feature value), normalized by its maximum over the neuron.
+    /// Nodes without a number in the column are black, but at least one must
+    /// have one.
+    pub fn sdf_with_extra(&self, column: usize) -> Result<Vec<Arc<dyn Object>>, Box<dyn Error>> {
+        if column < 8 {
+            return Err(
+                format!("column {column} is not an extended column, those start at 8").into(),
+            );
+        }
+        if self.nodes.iter().all(|n| n.extra(column).is_none()) {
+            return Err(format!("no node has a number in column {column}").into());
+        }
+
+        let value = |n: &Node| n.extra(column).unwrap_or(0.0).max(0.0);
+        let max = self.nodes.iter().map(value).fold(0.0, f32::max);
+        let max = if max > 0.0 { max } else { 1.0 };
+        let material = |n: &Node| {
+            let v = value(n) / max;
+            SolidColor::new(Vec3f::new(v, v, v))
+        };
+        Ok(self.sdf_with(material, |_, c| structure_label(c).into()))
+    }
+
+    /// Render with segments labelled by their structure type. Those owned by
+    /// nodes of undefined (0) or negative type could not be told from
+    /// background, so they are cut out as by `filter`, and the number of such
+    /// nodes is returned to warn about.
+    pub fn sdf_with_structures(&self) -> (Vec<Arc<dyn Object>>, usize) {
+        let labelled = |n: &Node| n.strcture > UNDEFINED;
+        let skipped = self.nodes.iter().filter(|n| !labelled(n)).count();
+        if skipped == 0 {
+            return (self.sdf(), 0);
+        }
+        let sdfs = self.filter(labelled).map_or(vec![], |swc| swc.sdf());
+        (sdfs, skipped)
+    }
+
+    /// Render every segment with the same label, e.g. the id of the neuron
+    /// among others in a volume.
+    pub fn sdf_with_label(&self, label: u32) -> Vec<Arc<dyn Object>> {
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        self.sdf_with(|_| white.clone(), |_, _| label.into())
+    }
+
+    /// Label every segment by the branch it belongs to, i.e. the section
+    /// between bifurcations, whose id is that of its first node: a root or a
+    /// child of a bifurcation. Ids must be positive to be told from background.
+    pub fn sdf_with_branch_ids(&self) -> Result<Vec<Arc<dyn Object>>, Box<dyn Error>> {
+        self.check_labels("id", |n| n.id)?;
+        let mut branches = vec![0; self.nodes.len()];
+        for &root in self.roots.iter() {
+            for i in self.preorder(root) {
+                branches[i] = match self.nodes[i].parent {
+                    Some(p) if !self.nodes[p].is_bifurcation() => branches[p],
+                    _ => id_label(&self.nodes[i]),
+                };
+            }
+        }
+
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        let index = |n: &Node| self.index[&n.id];
+        Ok(self.sdf_with(|_| white.clone(), |_, c| branches[index(c)].into()))
+    }
+
+    /// Label voxels by the id of the nearest node along their segment, the
+    /// segment being split in the middle. Ids must be positive to be told
+    /// from background.
+    pub fn sdf_with_node_ids(&self) -> Result<Vec<Arc<dyn Object>>, Box<dyn Error>> {
+        self.check_labels("id", |n| n.id)?;
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        Ok(self.sdf_with(
+            |_| white.clone(),
+            |n, c| Label::VAxisSplit(id_label(n), id_label(c)),
+        ))
+    }
+
+    /// Fail on the first node whose label, e.g. its id, would fall on
+    /// background.
+    fn check_labels<F>(&self, name: &str, label: F) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn(&Node) -> i32,
+    {
+        match self.nodes.iter().find(|n| label(n) <= 0) {
+            Some(n) => {
+                Err(format!("node {}: {name} {} is not a positive label", n.id, label(n)).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Build a SDF per segment, whose material is picked from the node that
+    /// owns the segment, i.e. the child, or the root of a single node tree,
+    /// and label from both the node and the owner, which are the same node for
+    /// spheres. Links reduced to the sphere of one end take the label of that
+    /// end when split. Somas are built as a whole, after their first node.
+    fn sdf_with<F, L>(&self, material: F, label: L) -> Vec<Arc<dyn Object>>
+    where
+        F: Fn(&Node) -> Arc<dyn Material>,
+        L: Fn(&Node, &Node) -> Label,
+    {
+        let mut out: Vec<Arc<dyn Object>> = vec![];
+        let mut in_soma = vec![false; self.nodes.len()];
+        for soma in self.somas() {
+            let head = &self.nodes[soma.nodes[0]];
+            out.push(SDFObject::new(
+                soma.sdf(),
+                material(head),
+                label(head, head),
+            ));
+            soma.nodes.iter().for_each(|&i| in_soma[i] = true);
+        }
+
+        for &root in self.roots.iter() {
+            for i in self.preorder(root) {
+                let n = &self.nodes[i];
+                // lone roots, and soma nodes to be labelled as soma, since
+                // the spheres are already in the links of their children
+                if (n.children.is_empty() && n.parent.is_none())
+                    || (n.strcture == SOMA && !in_soma[i])
+                {
+                    let sphere = Box::new(Sphere::new(n.xyz(), n.radius));
+                    out.push(SDFObject::new(sphere, material(n), label(n, n)));
+                }
+
+                for &j in n.children.iter() {
+                    let c = &self.nodes[j];
+                    let (link, sphere) = match (in_soma[i], in_soma[j]) {
+                        (true, true) => continue, // inside the soma
+                        // neurites leave the soma with their own radius
+                        (true, false) => {
+                            let cone = RoundCone::new(n.xyz(), c.radius, c.xyz(), c.radius);
+                            (Box::new(cone) as Box<dyn SDF>, None)
+                        }
+                        _ => link(n, c),
+                    };
+                    let label = match (label(n, c), sphere) {
+                        (Label::VAxisSplit(a, _), Some(End::Parent)) => Label::Constant(a),
+                        (Label::VAxisSplit(_, b), Some(End::Child)) => Label::Constant(b),
+                        (label, _) => label,
+                    };
+                    out.push(SDFObject::new(link, material(c), label));
+                }
+            }
         }
         out
     }
@@ -99,47 +373,537 @@ impl SWC {
     where
         F: Fn(f32) -> Vec3f,
     {
-        self.root.borrow().sdf_with_path_decay(decay_fn)
+        self.roots
+            .iter()
+            .flat_map(|&root| self.sdf_with_path_decay_from(root, &decay_fn))
+            .collect()
     }
 
-    fn node_impl(n: Rc<RefCell<Node>>, id: i32) -> Option<Rc<RefCell<Node>>> {
-        let nn = n.borrow();
-        if nn.id == id {
-            return Some(n.clone());
-        }
+    /// Walk the tree containing node `start` from it, through both parents
+    /// and children, with the colour decaying along the path length.
+    pub fn sdf_with_path_decay_from<F>(&self, start: usize, decay_fn: F) -> Vec<Arc<dyn Object>>
+    where
+        F: Fn(f32) -> Vec3f,
+    {
+        let mut visited = vec![false; self.nodes.len()];
+        visited[start] = true;
+        let mut stack = vec![(start, 0.0)];
+        let mut out: Vec<Arc<dyn Object>> = vec![];
+        while let Some((i, cur)) = stack.pop() {
+            let n = &self.nodes[i];
+            let a = decay_fn(cur);
+            let a_norm = a.norm();
+            for j in n.parent.iter().chain(n.children.iter()).copied() {
+                if visited[j] {
+                    continue;
+                }
+                visited[j] = true;
+
+                let m = &self.nodes[j];
+                let acc = cur + (n.xyz() - m.xyz()).norm();
+                let b = decay_fn(acc);
 
-        for c in &nn.children {
-            if let Some(a) = Self::node_impl(c.clone(), id) {
-                return Some(a);
+                const EPS: f32 = 1e-6;
+                if a_norm > EPS && b.norm() > EPS {
+                    let material = VAxisLinearGradient::new(a, b);
+                    let link = Box::new(RoundCone::new(n.xyz(), n.radius, m.xyz(), m.radius)); // TODO: perf
+                    let owner = if m.parent == Some(i) { m } else { n };
+                    out.push(SDFObject::new(link, material, structure_label(owner)));
+                }
+
+                stack.push((j, acc));
             }
         }
-        None
-    }
-
-    fn sdf_with_material_impl(
-        n: &Node,
-        material: Arc<dyn Material>,
-        out: &mut Vec<Arc<dyn Object>>,
-    ) {
-        for c in n.children.iter() {
-            let c = c.borrow();
-            let sdf: Box<dyn SDF> = if (n.xyz() - c.xyz()).norm() > f32::abs(n.radius - c.radius) {
-                Box::from(RoundCone::new(n.xyz(), n.radius, c.xyz(), c.radius))
-            } else if n.radius > c.radius {
-                Box::from(Sphere::new(n.xyz(), n.radius))
-            } else {
-                Box::from(Sphere::new(c.xyz(), c.radius))
-            };
-            let obj = SDFObject::new(sdf, material.clone());
-            out.push(obj);
+        out
+    }
+}
 
-            Self::sdf_with_material_impl(&c, material.clone(), out);
+/// End of a link reduced to its sphere.
+enum End {
+    Parent,
+    Child,
+}
+
+/// SDF of the segment between a node and its child, and the end it is
+/// reduced to when one sphere contains the other.
+fn link(n: &Node, c: &Node) -> (Box<dyn SDF>, Option<End>) {
+    if (n.xyz() - c.xyz()).norm() > f32::abs(n.radius - c.radius) {
+        let cone = RoundCone::new(n.xyz(), n.radius, c.xyz(), c.radius);
+        (Box::from(cone), None)
+    } else if n.radius > c.radius {
+        (Box::from(Sphere::new(n.xyz(), n.radius)), Some(End::Parent))
+    } else {
+        (Box::from(Sphere::new(c.xyz(), c.radius)), Some(End::Child))
+    }
+}
+
+/// Label by node id, ids below 1 fall on background unless checked first.
+fn id_label(n: &Node) -> u32 {
+    n.id.max(0) as u32
+}
+
+/// Label segments by the structure type of their owner, types below 1 fall
+/// on background unless checked first.
+fn structure_label(n: &Node) -> u32 {
+    n.strcture.max(0) as u32
+}
+
+/// How diagnostics are handled while reading.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseMode {
+    /// Fail on the first diagnostic.
+    Strict,
+    /// Skip offending records and collect all diagnostics.
+    Lenient,
+}
+
+fn parse_record(cells: &[&str], path: &Path, line: usize) -> Result<Node, SwcError> {
+    const N_COLUMNS: usize = 7;
+    if cells.len() < N_COLUMNS {
+        return Err(SwcError::MissingField {
+            path: path.to_path_buf(),
+            line,
+            column: cells.len() + 1,
+        });
+    }
+
+    let xyz = Vec3f::new(
+        parse_cell(cells, 3, path, line)?,
+        parse_cell(cells, 4, path, line)?,
+        parse_cell(cells, 5, path, line)?,
+    );
+    let mut node = Node::new(
+        parse_cell(cells, 1, path, line)?,
+        parse_cell(cells, 2, path, line)?,
+        xyz,
+        parse_cell(cells, 6, path, line)?,
+        parse_cell(cells, 7, path, line)?,
+    );
+
+    // extended columns, kept verbatim so timestamps are not rounded, only
+    // ESWC requires them to be numbers, others may hold anything
+    let eswc = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("eswc"));
+    for column in N_COLUMNS + 1..=cells.len() {
+        if eswc {
+            parse_cell::<f64>(cells, column, path, line)?;
         }
+        node.extra.push(cells[column - 1].to_string());
     }
+    Ok(node)
+}
+
+fn parse_cell<T>(cells: &[&str], column: usize, path: &Path, line: usize) -> Result<T, SwcError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let cell = cells[column - 1];
+    cell.parse().map_err(|err| SwcError::InvalidField {
+        path: path.to_path_buf(),
+        line,
+        column,
+        reason: format!("{err}: `{cell}`"),
+    })
+}
+
+/// Split a line into cells, accepting any run of whitespace (spaces, tabs) as
+/// delimiter, and dropping everything after a `#`, so comment lines, trailing
+/// comments and CRLF line endings are all handled alike.
+fn tokenize(line: &str) -> Vec<&str> {
+    let data = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    data.split_whitespace().collect()
 }
 
 impl fmt::Display for SWC {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Neuron with {} nodes", self.count)
+        write!(f, "Neuron with {} nodes", self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> SWC {
+        let path = Path::new("test.swc");
+        let (swc, diagnostics) =
+            SWC::parse(content.as_bytes(), path, ParseMode::Strict).expect("fails to parse swc");
+        assert!(diagnostics.is_empty());
+        swc
+    }
+
+    fn assert_nodes(swc: &SWC) {
+        assert!(swc.node(1).is_some() && swc.node(2).is_some());
+        let n = swc.node(3).expect("node not found");
+        assert_eq!((n.id, n.strcture, n.pid), (3, 3, 2));
+        assert_eq!((n.x, n.y, n.z, n.radius), (2.0, 0.5, -1.0, 0.25));
+    }
+
+    #[test]
+    fn single_space() {
+        assert_nodes(&parse(
+            "1 1 0 0 0 1 -1\n2 3 1 0 0 0.5 1\n3 3 2 0.5 -1 0.25 2\n",
+        ));
+    }
+
+    #[test]
+    fn tab_delimited() {
+        assert_nodes(&parse(
+            "1\t1\t0\t0\t0\t1\t-1\n2\t3\t1\t0\t0\t0.5\t1\n3\t3\t2\t0.5\t-1\t0.25\t2\n",
+        ));
+    }
+
+    #[test]
+    fn aligned_columns_and_leading_whitespace() {
+        assert_nodes(&parse(
+            "  1  1  0.0  0.0  0.0  1.00  -1\n  2  3  1.0  0.0  0.0  0.50   1\n  3  3  2.0  0.5 -1.0  0.25   2\n",
+        ));
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        assert_nodes(&parse(
+            "# header\r\n1 1 0 0 0 1 -1\r\n2 3 1 0 0 0.5 1\r\n3 3 2 0.5 -1 0.25 2\r\n",
+        ));
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        assert_nodes(&parse(
+            "# n type x y z r parent\n\n  # indented comment\n1 1 0 0 0 1 -1 # soma\n2 3 1 0 0 0.5 1#no space\n\t\n3 3 2 0.5 -1 0.25 2\n",
+        ));
+    }
+
+    #[test]
+    fn strict_fails_on_first_error() {
+        let content = "1 1 0 0 0 1 -1\n2 3 1.0.0 0 0 0.5 1\n3 3 2 0 0\n";
+        let err = SWC::parse(content.as_bytes(), Path::new("test.swc"), ParseMode::Strict)
+            .err()
+            .expect("should fail");
+        match err.downcast_ref::<SwcError>() {
+            Some(SwcError::InvalidField { line, column, .. }) => {
+                assert_eq!((*line, *column), (2, 3))
+            }
+            _ => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn lenient_collects_all_errors() {
+        let content =
+            "1 1 0 0 0 1 -1\n2 3 1.0.0 0 0 0.5 1\n3 3 2 0 0\n4 3 2 0 0 1 9\n5 3 2 0 0 1 1\n";
+        let (swc, diagnostics) = SWC::parse(
+            content.as_bytes(),
+            Path::new("test.swc"),
+            ParseMode::Lenient,
+        )
+        .expect("fails to parse swc");
+        assert!(swc.node(5).is_some());
+        assert!(matches!(
+            diagnostics[..],
+            [
+                SwcError::InvalidField {
+                    line: 2,
+                    column: 3,
+                    ..
+                },
+                SwcError::MissingField {
+                    line: 3,
+                    column: 6,
+                    ..
+                },
+                SwcError::ParentNotFound {
+                    line: 4,
+                    id: 4,
+                    pid: 9,
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn unsorted_nodes() {
+        assert_nodes(&parse(
+            "3 3 2 0.5 -1 0.25 2\n2 3 1 0 0 0.5 1\n1 1 0 0 0 1 -1\n",
+        ));
+    }
+
+    #[test]
+    fn dangling_parent() {
+        let content = "2 3 1 0 0 0.5 1\n3 3 2 0.5 -1 0.25 4\n1 1 0 0 0 1 -1\n";
+        let err = SWC::parse(content.as_bytes(), Path::new("test.swc"), ParseMode::Strict)
+            .err()
+            .expect("should fail");
+        assert!(matches!(
+            err.downcast_ref::<SwcError>(),
+            Some(SwcError::ParentNotFound {
+                line: 2,
+                id: 3,
+                pid: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn multiple_roots() {
+        let swc = parse("0 1 0 0 0 1 -1\n1 3 1 0 0 0.5 0\n5 2 9 9 9 1 -1\n6 2 8 9 9 1 5\n");
+        let roots: Vec<_> = swc.roots().iter().map(|&i| swc.nodes()[i].id).collect();
+        assert_eq!(roots, [0, 5]);
+        assert_eq!(swc.len(), 4);
+
+        // a link per tree, and a sphere labelling the soma node 0 as such
+        // inside the link to its neurite child, no other node is duplicated
+        let sdfs = swc.sdf();
+        assert_eq!(sdfs.len(), 3);
+        let labels = |x, y, z| -> Vec<u32> {
+            let p = Vec3f::new(x, y, z);
+            sdfs.iter()
+                .filter_map(|obj| Some(obj.label(p)?.1))
+                .collect()
+        };
+        assert_eq!(labels(0.0, 0.0, 0.0), [1, 3]);
+        assert_eq!(labels(1.25, 0.0, 0.0), [3]);
+        assert_eq!(labels(9.0, 9.0, 9.0), [2]);
+        assert_eq!(labels(8.0, 9.0, 9.0), [2]);
+    }
+
+    #[test]
+    fn invalid_topology() {
+        let content = "1 1 0 0 0 1 -1\n2 3 1 0 0 1 2\n3 3 1 0 0 1 5\n4 3 1 0 0 1 3\n5 3 1 0 0 1 4\n1 3 1 0 0 1 -1\n";
+        let (swc, diagnostics) = SWC::parse(
+            content.as_bytes(),
+            Path::new("test.swc"),
+            ParseMode::Lenient,
+        )
+        .expect("fails to parse swc");
+        assert_eq!(swc.roots().len(), 3);
+        assert!(matches!(
+            &diagnostics[..],
+            [
+                SwcError::DuplicateId { line: 6, id: 1, first_line: 1, .. },
+                SwcError::SelfParent { line: 2, id: 2, .. },
+                SwcError::Cycle { ids, .. },
+            ] if ids == &[3, 5, 4]
+        ));
+    }
+
+    #[test]
+    fn traversals() {
+        let swc =
+            parse("1 1 0 0 0 1 -1\n2 3 1 0 0 1 1\n3 3 2 0 0 1 2\n4 3 1 1 0 1 1\n5 3 1 2 0 1 4\n");
+        let ids = |it: &mut dyn Iterator<Item = usize>| -> Vec<i32> {
+            it.map(|i| swc.nodes()[i].id).collect()
+        };
+        assert_eq!(ids(&mut swc.preorder(0)), [1, 2, 3, 4, 5]);
+        assert_eq!(ids(&mut swc.postorder(0)), [3, 2, 5, 4, 1]);
+        assert_eq!(ids(&mut swc.bfs(0)), [1, 2, 4, 3, 5]);
+        assert_eq!(ids(&mut swc.preorder(3)), [4, 5]);
+    }
+
+    #[test]
+    fn deep_tree() {
+        let content: String = (1..=200_000)
+            .map(|i| format!("{i} 2 {i} 0 0 1 {}\n", if i == 1 { -1 } else { i - 1 }))
+            .collect();
+        let swc = parse(&content);
+        assert_eq!(swc.postorder(0).count(), 200_000);
+        assert_eq!(swc.sdf().len(), 199_999);
+    }
+
+    fn round_trip(content: &str) -> (SWC, SWC) {
+        let swc = parse(content);
+        let mut buf = Vec::new();
+        swc.write_to(&mut buf).expect("fails to write swc");
+        let written = String::from_utf8(buf).expect("invalid utf-8");
+        (swc, parse(&written))
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let content = "# ORIGINAL_SOURCE test\r\n#\n# SCALE 1.0 1.0 1.0\n3 3 2 0.5 -1 0.25 2\n1 1 0.1 -0.2 1e-3 1.5 -1\n2 3 1.125 0 0 0.5 1 # trailing\n";
+        let (a, b) = round_trip(content);
+        assert_eq!(
+            a.comments,
+            [" ORIGINAL_SOURCE test", "", " SCALE 1.0 1.0 1.0"]
+        );
+        assert_eq!(a.comments, b.comments);
+        assert_eq!(a.nodes(), b.nodes());
+        assert_eq!(a.roots(), b.roots());
+    }
+
+    #[test]
+    fn write_resolved_parents() {
+        let content = "1 1 0 0 0 1 -1\n2 3 1 0 0 1 9\n3 3 2 0 0 1 2\n";
+        let (swc, _) = SWC::parse(
+            content.as_bytes(),
+            Path::new("test.swc"),
+            ParseMode::Lenient,
+        )
+        .expect("fails to parse swc");
+        let mut buf = Vec::new();
+        swc.write_to(&mut buf).expect("fails to write swc");
+        let written = String::from_utf8(buf).expect("invalid utf-8");
+        assert_eq!(written, "1 1 0 0 0 1 -1\n2 3 1 0 0 1 -1\n3 3 2 0 0 1 2\n");
+    }
+
+    #[test]
+    fn header_metadata() {
+        let mut swc = parse("# ORIGINAL_SOURCE Neurolucida\n# some free text\n# SCALE 2 2 4\n# SHIFT: 1, 0, -1\n1 1 1 2 3 1 -1\n");
+        let metadata = swc.metadata();
+        assert_eq!(metadata.get("ORIGINAL_SOURCE"), Some("Neurolucida"));
+        assert_eq!(metadata.fields().len(), 3);
+
+        swc.apply_scale_shift();
+        swc.apply_scale_shift();
+        let n = swc.node(1).expect("node not found");
+        assert_eq!((n.x, n.y, n.z, n.radius), (3.0, 4.0, 11.0, 1.0));
+    }
+
+    #[test]
+    fn scale_shift_round_trip() {
+        let mut swc =
+            parse("# ORIGINAL_SOURCE test\n# SCALE 2 2 4\n#SHIFT: 1, 0, -1\n1 1 1 2 3 1 -1\n");
+        swc.apply_scale_shift();
+        assert_eq!(swc.comments, [" ORIGINAL_SOURCE test"]);
+
+        let mut buf = Vec::new();
+        swc.write_to(&mut buf).expect("fails to write swc");
+        let mut written = parse(&String::from_utf8(buf).expect("invalid utf-8"));
+        written.apply_scale_shift();
+        assert_eq!(written.nodes(), swc.nodes());
+        assert_eq!(written.metadata(), swc.metadata());
+    }
+
+    #[test]
+    fn geometry() {
+        let mut swc = parse("1 1 0 0 0 1 -1\n2 3 1 0 0 1 1\n");
+        swc.node_mut(2).expect("node not found").radius = 0.5;
+        swc.map_geometry(|n| (n.xyz() + Vec3f::new(0.0, 1.0, 0.0), n.radius * 2.0));
+        let n = swc.node(2).expect("node not found");
+        assert_eq!(
+            (n.x, n.y, n.z, n.radius, n.parent),
+            (1.0, 1.0, 0.0, 1.0, Some(0))
+        );
+    }
+
+    #[test]
+    fn eswc_columns() {
+        let content = "1 1 0 0 0 1 -1 0 0 0 1.5 0.25\n2 3 1 0 0 1 1 1 1 0 2 1\n3 3 2 0 0 1 2\n";
+        let (a, b) = round_trip(content);
+        assert_eq!(a.node(1).unwrap().extra, ["0", "0", "0", "1.5", "0.25"]);
+        assert_eq!(a.node(2).unwrap().extra(12), Some(1.0));
+        assert_eq!(a.node(3).unwrap().extra(12), None);
+        assert_eq!(a.nodes(), b.nodes());
+        let sdfs = a.sdf_with_extra(12).expect("fails to render column");
+        assert_eq!(sdfs.len(), 3); // with the soma sphere of node 1
+        assert!(a.sdf_with_extra(6).is_err());
+        assert!(a.sdf_with_extra(13).is_err());
+    }
+
+    #[test]
+    fn extra_columns() {
+        let content = "1 1 0 0 0 1 -1 soma 1700000000123\n";
+        let parse_as = |fname| SWC::parse(content.as_bytes(), Path::new(fname), ParseMode::Strict);
+        let (swc, _) = parse_as("test.swc").expect("fails to parse swc");
+        let n = swc.node(1).expect("node not found");
+        assert_eq!(n.extra, ["soma", "1700000000123"]);
+        assert_eq!((n.extra(8), n.extra(9)), (None, Some(1700000000123.0)));
+        let mut buf = Vec::new();
+        swc.write_to(&mut buf).expect("fails to write swc");
+        assert_eq!(String::from_utf8(buf).unwrap(), content);
+
+        let err = parse_as("test.eswc").err().expect("should fail");
+        assert!(matches!(
+            err.downcast_ref::<SwcError>(),
+            Some(SwcError::InvalidField { column: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn branch_and_node_ids() {
+        use crate::render::{ObjectsScene, Scene};
+
+        let swc = parse(
+            "1 1 0 0 0 3 -1\n2 3 10 0 0 1 1\n3 3 20 0 0 1 2\n4 3 30 5 0 1 3\n5 3 30 -5 0 1 3\n",
+        );
+        let scene = |sdfs: Vec<Arc<dyn Object>>| {
+            let mut scene = ObjectsScene::new();
+            sdfs.into_iter().for_each(|sdf| scene.add(sdf));
+            scene.build_bvh();
+            scene
+        };
+        let labels = |scene: &ObjectsScene| {
+            [
+                (0.0, 0.0),
+                (12.0, 0.0),
+                (18.0, 0.0),
+                (29.0, 4.5),
+                (29.0, -4.5),
+            ]
+            .map(|(x, y)| scene.label(Vec3f::new(x, y, 0.0)))
+        };
+        let branches = swc.sdf_with_branch_ids().expect("fails to label branches");
+        assert_eq!(labels(&scene(branches)), [1, 1, 1, 4, 5]);
+        let nodes = swc.sdf_with_node_ids().expect("fails to label nodes");
+        assert_eq!(labels(&scene(nodes)), [1, 2, 3, 4, 5]);
+
+        // the sphere of a child containing its parent is labelled by the child
+        let swc = parse("1 3 0 0 0 1 -1\n2 3 1 0 0 3 1\n3 3 10 0 0 1 2\n");
+        let nodes = swc.sdf_with_node_ids().expect("fails to label nodes");
+        let scene = scene(nodes);
+        let label = |x| scene.label(Vec3f::new(x, 0.0, 0.0));
+        assert_eq!([label(0.0), label(2.0), label(9.0)], [2, 2, 3]);
+    }
+
+    #[test]
+    fn path_decay_gradient() {
+        // gradients are shaded by u, i.e. inside links by the colour of their
+        // far end, so the v axis does not change them
+        let swc = parse("1 3 10 5 0 1 -1\n2 3 14 5 0 1 1\n3 3 14 9 0 1 2\n");
+        let decay = |a: f32| Vec3f::new(1.0, 1.0, 1.0) * (1.0 - a / 16.0);
+        let sdfs = swc.sdf_with_path_decay(decay);
+        let colors = |x, y| -> Vec<f32> {
+            let p = Vec3f::new(x, y, 0.0);
+            sdfs.iter().filter_map(|obj| Some(obj.hit(p)?.x)).collect()
+        };
+        assert_eq!(colors(10.5, 5.0), [0.75]);
+        assert_eq!(colors(13.5, 5.0), [0.75, 0.5]);
+        assert_eq!(colors(14.0, 8.5), [0.5]);
+    }
+
+    #[test]
+    fn labels_below_one() {
+        let swc = parse("0 1 0 0 0 1 -1\n1 3 1 0 0 0.5 0\n");
+        assert_eq!(swc.sdf_with_structures().1, 0);
+        assert!(swc.sdf_with_branch_ids().is_err());
+        assert!(swc.sdf_with_node_ids().is_err());
+        let swc = parse("1 0 0 0 0 1 -1\n2 3 1 0 0 0.5 1\n");
+        assert!(swc.sdf_with_node_ids().is_ok());
+    }
+
+    #[test]
+    fn undefined_structures() {
+        use crate::render::{ObjectsScene, Scene};
+
+        // the segment owned by the undefined node 2 is cut out, the others
+        // keep their labels
+        let swc = parse("1 1 0 0 0 2 -1\n2 0 10 0 0 1 1\n3 3 20 0 0 1 2\n");
+        let (sdfs, skipped) = swc.sdf_with_structures();
+        assert_eq!(skipped, 1);
+        let mut scene = ObjectsScene::new();
+        sdfs.into_iter().for_each(|sdf| scene.add(sdf));
+        scene.build_bvh();
+        let labels = [0.0, 5.0, 15.0].map(|x| scene.label(Vec3f::new(x, 0.0, 0.0)));
+        assert_eq!(labels, [1, 0, 3]);
+
+        let swc = parse("1 0 0 0 0 1 -1\n");
+        assert_eq!(swc.sdf_with_structures().1, 1);
     }
 }
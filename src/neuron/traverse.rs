@@ -0,0 +1,84 @@
+use super::node::Node;
+use std::collections::VecDeque;
+
+/// Depth-first traversal visiting a node before its children.
+pub struct PreOrder<'a> {
+    nodes: &'a [Node],
+    stack: Vec<usize>,
+}
+
+impl<'a> PreOrder<'a> {
+    pub fn new(nodes: &'a [Node], start: usize) -> PreOrder<'a> {
+        PreOrder {
+            nodes,
+            stack: vec![start],
+        }
+    }
+}
+
+impl Iterator for PreOrder<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self.stack.pop()?;
+        self.stack.extend(self.nodes[i].children.iter().rev());
+        Some(i)
+    }
+}
+
+/// Depth-first traversal visiting a node after all its children.
+pub struct PostOrder<'a> {
+    nodes: &'a [Node],
+    stack: Vec<(usize, bool)>, // (index, children pushed)
+}
+
+impl<'a> PostOrder<'a> {
+    pub fn new(nodes: &'a [Node], start: usize) -> PostOrder<'a> {
+        PostOrder {
+            nodes,
+            stack: vec![(start, false)],
+        }
+    }
+}
+
+impl Iterator for PostOrder<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((i, expanded)) = self.stack.pop() {
+            if expanded {
+                return Some(i);
+            }
+
+            self.stack.push((i, true));
+            let children = self.nodes[i].children.iter().rev();
+            self.stack.extend(children.map(|&c| (c, false)));
+        }
+        None
+    }
+}
+
+/// Breadth-first traversal, level by level.
+pub struct Bfs<'a> {
+    nodes: &'a [Node],
+    queue: VecDeque<usize>,
+}
+
+impl<'a> Bfs<'a> {
+    pub fn new(nodes: &'a [Node], start: usize) -> Bfs<'a> {
+        Bfs {
+            nodes,
+            queue: VecDeque::from([start]),
+        }
+    }
+}
+
+impl Iterator for Bfs<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self.queue.pop_front()?;
+        self.queue.extend(self.nodes[i].children.iter());
+        Some(i)
+    }
+}
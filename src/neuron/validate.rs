@@ -0,0 +1,132 @@
+use super::{error_kind::SwcError, node::Node, swc::ParseMode};
+use std::{collections::HashMap, path::Path};
+
+pub struct Diagnostics {
+    mode: ParseMode,
+    pub errors: Vec<SwcError>,
+}
+
+impl Diagnostics {
+    pub fn new(mode: ParseMode) -> Diagnostics {
+        Diagnostics {
+            mode,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn report(&mut self, err: SwcError) -> Result<(), SwcError> {
+        match self.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => {
+                self.errors.push(err);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Drop records whose id is already taken, keeping the first one.
+pub fn dedup(
+    records: Vec<(usize, Node)>,
+    path: &Path,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<(usize, Node)>, SwcError> {
+    let mut first_lines = HashMap::new();
+    let mut out = Vec::with_capacity(records.len());
+    for (line, node) in records {
+        if let Some(&first_line) = first_lines.get(&node.id) {
+            diagnostics.report(SwcError::DuplicateId {
+                path: path.to_path_buf(),
+                line,
+                id: node.id,
+                first_line,
+            })?;
+            continue;
+        }
+
+        first_lines.insert(node.id, line);
+        out.push((line, node));
+    }
+    Ok(out)
+}
+
+/// Resolve the parent index of every record, `None` for roots. In lenient
+/// mode, self-parented and dangling nodes become roots, and every cycle is
+/// broken at its first record in file order.
+pub fn resolve_parents(
+    records: &[(usize, Node)],
+    path: &Path,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<Option<usize>>, SwcError> {
+    let index: HashMap<i32, usize> = records
+        .iter()
+        .enumerate()
+        .map(|(i, (_, n))| (n.id, i))
+        .collect();
+
+    let mut parents = Vec::with_capacity(records.len());
+    for (line, node) in records.iter() {
+        let (line, id, pid) = (*line, node.id, node.pid);
+        let parent = match pid {
+            -1 => None,
+            _ if pid == id => {
+                let path = path.to_path_buf();
+                diagnostics.report(SwcError::SelfParent { path, line, id })?;
+                None
+            }
+            _ => match index.get(&pid) {
+                Some(&i) => Some(i),
+                None => {
+                    let path = path.to_path_buf();
+                    diagnostics.report(SwcError::ParentNotFound {
+                        path,
+                        line,
+                        id,
+                        pid,
+                    })?;
+                    None
+                }
+            },
+        };
+        parents.push(parent);
+    }
+
+    // walk up from every node, a walk which meets itself is a cycle
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Unvisited,
+        Visiting,
+        Done,
+    }
+
+    let mut states = vec![State::Unvisited; records.len()];
+    for start in 0..records.len() {
+        let mut trail: Vec<usize> = vec![];
+        let mut cur = Some(start);
+        while let Some(i) = cur {
+            match states[i] {
+                State::Done => break,
+                State::Visiting => {
+                    let pos = trail.iter().position(|&j| j == i).unwrap();
+                    let cycle = &trail[pos..];
+                    diagnostics.report(SwcError::Cycle {
+                        path: path.to_path_buf(),
+                        ids: cycle.iter().map(|&j| records[j].1.id).collect(),
+                    })?;
+                    parents[*cycle.iter().min().unwrap()] = None;
+                    break;
+                }
+                State::Unvisited => {
+                    states[i] = State::Visiting;
+                    trail.push(i);
+                    cur = parents[i];
+                }
+            }
+        }
+
+        for i in trail {
+            states[i] = State::Done;
+        }
+    }
+    Ok(parents)
+}
@@ -1,14 +1,20 @@
 mod accelerator;
 mod anti_aliasing;
 mod material;
+mod noise;
 mod object;
+mod pixel;
+mod psf;
 mod renderer;
 mod scene;
 mod tiff;
 
-pub use self::tiff::TiffWriter;
-pub use anti_aliasing::Msaa;
+pub use self::tiff::{write_stack, TiffWriter};
+pub use anti_aliasing::{hash, Coverage, Msaa, Pattern};
 pub use material::{Material, SolidColor, VAxisLinearGradient};
-pub use object::{Object, SDFObject};
-pub use renderer::{ImageStackRenderer, Renderer};
-pub use scene::{ObjectsScene, Scene};
+pub use noise::NoiseRenderer;
+pub use object::{Label, Object, SDFObject};
+pub use pixel::{Depth, Image};
+pub use psf::{Psf, PsfRenderer};
+pub use renderer::{ImageStackRenderer, RenderMode, Renderer};
+pub use scene::{ObjectsScene, Overlap, Scene};
@@ -1,9 +1,11 @@
-use super::Object;
+use super::{Object, Overlap};
 use crate::vec::{self, Vec3f};
 use std::{cmp::Ordering, sync::Arc};
 
 pub trait Accelerator: Send + Sync {
     fn hit(&self, p: Vec3f) -> Option<Vec3f>;
+    fn label(&self, p: Vec3f, overlap: Overlap) -> Option<u32>;
+    fn signed_distance(&self, p: Vec3f, max: f32) -> f32;
     fn bounding_box(&self) -> Option<(Vec3f, Vec3f)>;
 }
 
@@ -32,6 +34,36 @@ impl Accelerator for BVH {
         }
     }
 
+    fn label(&self, p: Vec3f, overlap: Overlap) -> Option<u32> {
+        let candidates = self.root.as_ref()?.hit(p);
+        overlap.resolve(candidates.filter_map(|idx| Some((idx, self.objects[idx].label(p)?))))
+    }
+
+    /// Nearest first traversal, skipping nodes farther than the nearest object
+    /// found so far, or not containing `p` once inside an object.
+    fn signed_distance(&self, p: Vec3f, max: f32) -> f32 {
+        let mut best = max;
+        let mut stack: Vec<_> = self.root.iter().collect();
+        while let Some(node) = stack.pop() {
+            if node.distance(p) > best.max(0.0) {
+                continue;
+            }
+
+            match &node.children {
+                Some((left, right)) if left.distance(p) < right.distance(p) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                Some((left, right)) => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+                None => best = best.min(self.objects[node.index].signed_distance(p)),
+            }
+        }
+        best
+    }
+
     fn bounding_box(&self) -> Option<(Vec3f, Vec3f)> {
         match &self.root {
             Some(r) => Some(r.bounding_box),
@@ -97,6 +129,13 @@ impl BVHNode {
         BVHHitIter { s: vec![&self], p }
     }
 
+    /// Distance from `p` to the bounding box, 0 inside.
+    fn distance(&self, p: Vec3f) -> f32 {
+        let (min, max) = self.bounding_box;
+        let zero = Vec3f::new(0.0, 0.0, 0.0);
+        vec::maximum(vec::maximum(min - p, p - max), zero).norm()
+    }
+
     fn isin(&self, p: Vec3f) -> bool {
         let (min, max) = self.bounding_box;
         p.x >= min.x && p.y >= min.y && p.z >= min.z && p.x <= max.x && p.y <= max.y && p.z <= max.z
@@ -1,50 +1,404 @@
+use super::Scene;
 use crate::vec::Vec3f;
-use std::collections::HashMap;
 
-#[derive(Hash, PartialEq, Eq, Clone, Copy)]
-pub enum Msaa {
-    Disable = 1,
-    Oct = 8,
-    TwentySeven = 27,
-    SixtyFour = 64,
+/// Distribution of the sample points in a voxel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pattern {
+    /// Regular grid, as close to cubic as the number of samples allows.
+    Grid,
+    /// A random point in each cell of the grid, drawn again for each voxel.
+    Jitter,
+    /// Halton sequence in bases 2, 3 and 5.
+    Halton,
+    /// Sobol sequence.
+    Sobol,
 }
 
-impl TryFrom<i32> for Msaa {
+impl TryFrom<&str> for Pattern {
     type Error = &'static str;
 
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
-            1 => Ok(Msaa::Disable),
-            8 => Ok(Msaa::Oct),
-            27 => Ok(Msaa::TwentySeven),
-            64 => Ok(Msaa::SixtyFour),
-            _ => Err("msaa only support 1/8/27"),
+            "grid" => Ok(Pattern::Grid),
+            "jitter" => Ok(Pattern::Jitter),
+            "halton" => Ok(Pattern::Halton),
+            "sobol" => Ok(Pattern::Sobol),
+            _ => Err("pattern only support grid/jitter/halton/sobol"),
         }
     }
 }
 
+/// Sample points of the voxels, as offsets in the unit cube.
+#[derive(Clone, Debug)]
+pub struct Msaa {
+    pattern: Pattern,
+    seed: u64,
+    points: Vec<Vec3f>,
+    cell: Vec3f, // size of the jitter cells
+}
+
+impl Msaa {
+    pub fn new(samples: usize, pattern: Pattern) -> Result<Msaa, &'static str> {
+        if samples == 0 {
+            return Err("msaa needs at least one sample");
+        }
+
+        let (points, cell) = match pattern {
+            Pattern::Grid | Pattern::Jitter => {
+                let (nx, ny, nz) = grid_size(samples);
+                let n = Vec3f::new(nx as f32, ny as f32, nz as f32);
+                let points = (0..samples).map(|i| {
+                    let j = Vec3f::new(
+                        (i / (ny * nz)) as f32,
+                        (i / nz % ny) as f32,
+                        (i % nz) as f32,
+                    );
+                    match pattern {
+                        Pattern::Grid => (j + 1.0) / (n + 1.0),
+                        _ => j / n,
+                    }
+                });
+                let cell = match pattern {
+                    Pattern::Grid => Vec3f::new(0.0, 0.0, 0.0),
+                    _ => Vec3f::new(1.0, 1.0, 1.0) / n,
+                };
+                (points.collect(), cell)
+            }
+            Pattern::Halton | Pattern::Sobol => {
+                // shifted by half a stratum so a single sample is the centre
+                let shift = 0.5 / samples as f32;
+                let points = (0..samples).map(|i| {
+                    let p = match pattern {
+                        Pattern::Halton => halton(i as u32),
+                        _ => sobol(i as u32),
+                    };
+                    Vec3f::new(
+                        (p.x + shift).fract(),
+                        (p.y + shift).fract(),
+                        (p.z + shift).fract(),
+                    )
+                });
+                (points.collect(), Vec3f::new(0.0, 0.0, 0.0))
+            }
+        };
+        Ok(Msaa {
+            pattern,
+            seed: 0,
+            points,
+            cell,
+        })
+    }
+
+    /// Seed of the stochastic patterns, the same seed gives the same points.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Sample points of the voxel at `index`.
+    pub fn points(&self, index: [u32; 3]) -> impl Iterator<Item = Vec3f> + '_ {
+        let key = (self.pattern == Pattern::Jitter).then(|| {
+            let [x, y, z] = index.map(u64::from);
+            hash(self.seed ^ hash(x | y << 21 | z << 42))
+        });
+        self.points
+            .iter()
+            .enumerate()
+            .map(move |(i, &p)| match key {
+                Some(key) => {
+                    let k = hash(key ^ i as u64);
+                    let u = Vec3f::new(unit(k), unit(hash(k)), unit(hash(k ^ 1)));
+                    p + self.cell * u
+                }
+                None => p,
+            })
+    }
+}
+
+impl Default for Msaa {
+    fn default() -> Self {
+        Msaa::new(1, Pattern::Grid).unwrap()
+    }
+}
+
 lazy_static! {
-    static ref DISABLE: [Vec3f; 1] = divided_equally::<1>();
-    static ref OCT: [Vec3f; 8] = divided_equally::<8>();
-    static ref TWENTY_SEVEN: [Vec3f; 27] = divided_equally::<27>();
-    static ref SIXTY_FOUR: [Vec3f; 64] = divided_equally::<64>();
-    pub static ref MSAA_OPTIONS: HashMap<Msaa, &'static [Vec3f]> = {
-        let mut map = HashMap::new();
-        map.insert(Msaa::Disable, DISABLE.as_slice());
-        map.insert(Msaa::Oct, OCT.as_slice());
-        map.insert(Msaa::TwentySeven, TWENTY_SEVEN.as_slice());
-        map.insert(Msaa::SixtyFour, SIXTY_FOUR.as_slice());
-        map
-    };
+    /// Direction numbers of the first three Sobol dimensions, from the
+    /// primitive polynomials 1, x + 1 and x^2 + x + 1.
+    static ref SOBOL: [[u32; 32]; 3] = [
+        sobol_directions(0, 0, &[]),
+        sobol_directions(1, 0, &[1]),
+        sobol_directions(2, 1, &[1, 3]),
+    ];
+}
+
+fn sobol_directions(s: usize, a: u32, m: &[u32]) -> [u32; 32] {
+    let mut v = [0; 32];
+    for k in 0..32 {
+        v[k] = match k {
+            _ if s == 0 => 1 << (31 - k),
+            _ if k < s => m[k] << (31 - k),
+            _ => {
+                let mut x = v[k - s] ^ (v[k - s] >> s);
+                for j in 1..s {
+                    x ^= ((a >> (s - 1 - j)) & 1) * v[k - j];
+                }
+                x
+            }
+        };
+    }
+    v
+}
+
+fn sobol(i: u32) -> Vec3f {
+    let [x, y, z] = SOBOL.map(|v| {
+        let bits = (0..32).filter(|k| (i >> k) & 1 == 1);
+        bits.fold(0, |acc, k| acc ^ v[k]) as f32 / 2f32.powi(32)
+    });
+    Vec3f::new(x, y, z)
+}
+
+fn halton(i: u32) -> Vec3f {
+    let [x, y, z] = [2, 3, 5].map(|base| radical_inverse(i, base));
+    Vec3f::new(x, y, z)
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let (mut r, mut f) = (0.0, 1.0 / base as f32);
+    while i > 0 {
+        r += (i % base) as f32 * f;
+        i /= base;
+        f /= base as f32;
+    }
+    r
+}
+
+/// Factors of `n` along x, y and z, as close to each other as possible and
+/// the smallest along z, usually the coarsest axis.
+fn grid_size(n: usize) -> (usize, usize, usize) {
+    let mut best = (n, 1, 1);
+    for a in (1..=n).filter(|a| n.is_multiple_of(*a)) {
+        for b in (1..=n / a).filter(|b| (n / a).is_multiple_of(*b)) {
+            let c = n / a / b;
+            if a + b + c < best.0 + best.1 + best.2 && a >= b && b >= c {
+                best = (a, b, c);
+            }
+        }
+    }
+    best
+}
+
+/// SplitMix64 finalizer, to draw reproducible numbers from a key.
+pub fn hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Uniform number in [0, 1).
+fn unit(x: u64) -> f32 {
+    (x >> 40) as f32 / (1u64 << 24) as f32
 }
 
-fn divided_equally<const N: usize>() -> [Vec3f; N] {
-    let k = f32::cbrt(N as f32) as usize;
-    core::array::from_fn(|i| {
-        Vec3f::new(
-            (((i / (k * k)) % 2) + 1) as f32 / (k + 1) as f32,
-            (((i / k) % 2) + 1) as f32 / (k + 1) as f32,
-            (((i) % 2) + 1) as f32 / (k + 1) as f32,
-        )
-    })
+/// How the part of a voxel covered by the scene is estimated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Coverage {
+    /// Fraction of the MSAA sample points inside.
+    Samples,
+    /// From the signed distance at the voxel centre over the width of the
+    /// voxel across the surface, at the cost of about a single sample.
+    Analytic,
+    /// MSAA samples only for voxels within a diagonal of the surface, and
+    /// the voxel centre elsewhere. Label boundaries inside objects, e.g.
+    /// between branches, are not supersampled away from the surface.
+    Adaptive,
+}
+
+impl TryFrom<&str> for Coverage {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "samples" => Ok(Coverage::Samples),
+            "analytic" => Ok(Coverage::Analytic),
+            "adaptive" => Ok(Coverage::Adaptive),
+            _ => Err("coverage only support samples/analytic/adaptive"),
+        }
+    }
+}
+
+/// A voxel split by the surface of the scene, taken as a plane across it.
+pub struct PartialVolume {
+    /// Fraction of the voxel inside.
+    pub coverage: f32,
+    /// Points just inside and outside the surface, near the voxel centre.
+    pub inside: Vec3f,
+    pub outside: Vec3f,
+}
+
+impl PartialVolume {
+    /// Estimate the partial volume of the voxel of size `r` centred at `c`.
+    ///
+    /// The plane crosses the voxel at the signed distance of `c`, and the
+    /// voxel spans `|n.x| r.x + |n.y| r.y + |n.z| r.z` along its normal `n`,
+    /// so anisotropic voxels get a coverage ramp matching their extent.
+    pub fn new(scene: &dyn Scene, c: Vec3f, r: Vec3f) -> PartialVolume {
+        let half = r.norm() / 2.0;
+        let max = 4.0 * half; // bounds every query below
+        let sd = scene.signed_distance(c, max);
+        if sd.abs() >= half {
+            let coverage = if sd < 0.0 { 1.0 } else { 0.0 };
+            return PartialVolume {
+                coverage,
+                inside: c,
+                outside: c,
+            };
+        }
+
+        let n = normal(scene, c, 0.01 * half, max);
+        let width = n.x.abs() * r.x + n.y.abs() * r.y + n.z.abs() * r.z;
+        let eps = 0.01 * width;
+        PartialVolume {
+            coverage: (0.5 - sd / width).clamp(0.0, 1.0),
+            inside: c - n * (sd.max(0.0) + eps),
+            outside: c + n * ((-sd).max(0.0) + eps),
+        }
+    }
+
+    /// Points weighted by the fraction of the voxel they stand for.
+    pub fn samples(&self) -> [(Vec3f, f32); 2] {
+        [
+            (self.inside, self.coverage),
+            (self.outside, 1.0 - self.coverage),
+        ]
+    }
+}
+
+/// Gradient of the signed distance, by central differences on a tetrahedron.
+fn normal(scene: &dyn Scene, p: Vec3f, h: f32, max: f32) -> Vec3f {
+    let g = [
+        (1.0, -1.0, -1.0),
+        (-1.0, -1.0, 1.0),
+        (-1.0, 1.0, -1.0),
+        (1.0, 1.0, 1.0),
+    ]
+    .map(|(x, y, z)| Vec3f::new(x, y, z))
+    .into_iter()
+    .fold(Vec3f::new(0.0, 0.0, 0.0), |acc, k| {
+        acc + k * scene.signed_distance(p + k * h, max)
+    });
+    let len = g.norm();
+    if len > 0.0 {
+        g / len
+    } else {
+        Vec3f::new(1.0, 0.0, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{ObjectsScene, SDFObject, SolidColor},
+        sdf::Sphere,
+    };
+
+    #[test]
+    fn sample_patterns() {
+        assert_eq!(grid_size(27), (3, 3, 3));
+        assert_eq!(grid_size(12), (3, 2, 2));
+        assert_eq!(grid_size(7), (7, 1, 1));
+
+        for pattern in [
+            Pattern::Grid,
+            Pattern::Jitter,
+            Pattern::Halton,
+            Pattern::Sobol,
+        ] {
+            for n in [1, 8, 27, 64, 10] {
+                let msaa = Msaa::new(n, pattern).unwrap();
+                let points: Vec<_> = msaa.points([1, 2, 3]).collect();
+                assert_eq!(points.len(), n);
+                for (i, a) in points.iter().enumerate() {
+                    assert!([a.x, a.y, a.z].iter().all(|v| (0.0..1.0).contains(v)));
+                    assert!(points[..i].iter().all(|b| (*a - *b).norm() > 1e-6));
+                }
+            }
+        }
+        let center = Msaa::new(1, Pattern::Sobol)
+            .unwrap()
+            .points([0, 0, 0])
+            .next();
+        assert_eq!(center.unwrap().tuple(), (0.5, 0.5, 0.5));
+
+        let sobol: Vec<_> = (0..4).map(|i| sobol(i).tuple()).collect();
+        let expected = [
+            (0.0, 0.0, 0.0),
+            (0.5, 0.5, 0.5),
+            (0.25, 0.75, 0.75),
+            (0.75, 0.25, 0.25),
+        ];
+        assert_eq!(sobol, expected);
+
+        let mut msaa = Msaa::new(8, Pattern::Jitter).unwrap();
+        let a: Vec<_> = msaa.points([1, 2, 3]).map(|p| p.tuple()).collect();
+        assert_eq!(
+            a,
+            msaa.points([1, 2, 3])
+                .map(|p| p.tuple())
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(
+            a,
+            msaa.points([2, 2, 3])
+                .map(|p| p.tuple())
+                .collect::<Vec<_>>()
+        );
+        msaa.set_seed(42);
+        assert_ne!(
+            a,
+            msaa.points([1, 2, 3])
+                .map(|p| p.tuple())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn analytic_coverage() {
+        let mut scene = ObjectsScene::new();
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        let sphere = Box::new(Sphere::new(Vec3f::new(0.0, 0.0, 0.0), 10.0));
+        scene.add(SDFObject::new(sphere, white, 1));
+        scene.build_bvh();
+
+        let cases = [
+            (10.0, (1.0, 1.0, 1.0), 0.5),
+            (10.25, (1.0, 1.0, 1.0), 0.25),
+            (9.5, (1.0, 1.0, 1.0), 1.0),
+            (10.5, (2.0, 1.0, 1.0), 0.25), // wider along x
+            (10.5, (1.0, 2.0, 2.0), 0.0),
+            (0.0, (1.0, 1.0, 1.0), 1.0),
+            (20.0, (1.0, 1.0, 1.0), 0.0),
+        ];
+        for (x, (rx, ry, rz), expected) in cases {
+            let c = Vec3f::new(x, 0.0, 0.0);
+            let pv = PartialVolume::new(&scene, c, Vec3f::new(rx, ry, rz));
+            assert!(
+                (pv.coverage - expected).abs() < 0.02,
+                "at {x}: {}",
+                pv.coverage
+            );
+        }
+
+        let pv = PartialVolume::new(
+            &scene,
+            Vec3f::new(10.25, 0.0, 0.0),
+            Vec3f::new(1.0, 1.0, 1.0),
+        );
+        assert_eq!(scene.label(pv.inside), 1);
+        assert_eq!(scene.label(pv.outside), 0);
+    }
 }
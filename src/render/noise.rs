@@ -0,0 +1,184 @@
+use super::{
+    pixel::{map_volumes, Depth},
+    renderer::{Images, Renderer},
+    Psf, Scene,
+};
+use crate::vec::Vec3f;
+use rand::{rngs::StdRng, Rng, RngExt, SeedableRng};
+use rand_distr::{Distribution, Poisson, StandardNormal};
+use std::sync::Arc;
+
+/// Post-render stage simulating the noise and background of a microscope,
+/// in units of the full scale of the stack, i.e. intensities in [0, 1]. The
+/// whole stack is kept in memory, and the same seed gives the same noise.
+pub struct NoiseRenderer {
+    renderer: Box<dyn Renderer>,
+    photons: Option<f32>,
+    read_noise: f32,
+    background: Option<(f32, f32)>,
+    drift: f32,
+    attenuation: Option<f32>,
+    seed: u64,
+    depth: Option<Depth>,
+}
+
+impl NoiseRenderer {
+    pub fn new(renderer: Box<dyn Renderer>) -> NoiseRenderer {
+        NoiseRenderer {
+            renderer,
+            photons: None,
+            read_noise: 0.0,
+            background: None,
+            drift: 0.0,
+            attenuation: None,
+            seed: 0,
+            depth: None,
+        }
+    }
+
+    /// Poisson shot noise, with `photons` detected at full scale.
+    pub fn set_shot_noise(&mut self, photons: f32) {
+        self.photons = Some(photons);
+    }
+
+    /// Gaussian read noise, by its standard deviation.
+    pub fn set_read_noise(&mut self, sigma: f32) {
+        self.read_noise = sigma;
+    }
+
+    /// Autofluorescence of mean `level`, varying by about half of it over
+    /// structures of `scale` voxels.
+    pub fn set_background(&mut self, level: f32, scale: f32) {
+        self.background = Some((level, scale));
+    }
+
+    /// Gain drifting from slice to slice as a random walk, by the standard
+    /// deviation of its steps.
+    pub fn set_drift(&mut self, sigma: f32) {
+        self.drift = sigma;
+    }
+
+    /// Intensity decaying by a factor e every `length` slices along z.
+    pub fn set_attenuation(&mut self, length: f32) {
+        self.attenuation = Some(length);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Depth of the output, that of the rendered stack by default.
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = Some(depth);
+    }
+
+    fn apply(&self, volume: &mut [f32], size: [usize; 3], rng: &mut impl Rng) {
+        let background = self.background.map(|(level, scale)| {
+            let field = smooth_noise(size, scale, rng);
+            field
+                .into_iter()
+                .map(|g| level * (1.0 + 0.5 * g).max(0.0))
+                .collect::<Vec<_>>()
+        });
+
+        let plane = size[0] * size[1];
+        let mut gain: f32 = 1.0;
+        for z in 0..size[2] {
+            let attenuation = self.attenuation.map_or(1.0, |l| f32::exp(-(z as f32) / l));
+            let k = gain.max(0.0) * attenuation;
+            for i in z * plane..(z + 1) * plane {
+                let mut v = k * volume[i] + background.as_ref().map_or(0.0, |a| a[i]);
+                if let Some(photons) = self.photons {
+                    v = match Poisson::new(v * photons) {
+                        Ok(poisson) => poisson.sample(rng) / photons,
+                        Err(_) => 0.0, // no photon
+                    };
+                }
+                if self.read_noise > 0.0 {
+                    v += self.read_noise * rng.sample::<f32, _>(StandardNormal);
+                }
+                volume[i] = v.max(0.0);
+            }
+            gain += self.drift * rng.sample::<f32, _>(StandardNormal);
+        }
+    }
+}
+
+impl Renderer for NoiseRenderer {
+    fn scene(&self) -> Arc<dyn Scene> {
+        self.renderer.scene()
+    }
+
+    fn image_stack<'a>(&'a self) -> Images<'a> {
+        let stack: Vec<_> = self.renderer.image_stack().collect();
+        let scale = stack.first().map_or(1.0, |a| a.full_scale());
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let stack = map_volumes(&stack, self.depth, |volumes, size| {
+            for volume in volumes.iter_mut() {
+                volume.iter_mut().for_each(|v| *v /= scale);
+                self.apply(volume, size, &mut rng);
+                volume.iter_mut().for_each(|v| *v *= scale);
+            }
+        });
+        Box::new(stack.into_iter())
+    }
+}
+
+/// White noise blurred over `scale` voxels, with zero mean and unit standard
+/// deviation.
+fn smooth_noise(size: [usize; 3], scale: f32, rng: &mut impl Rng) -> Vec<f32> {
+    let len = size.iter().product();
+    let mut field: Vec<f32> = (0..len).map(|_| rng.random::<f32>() - 0.5).collect();
+    let mut weight = vec![1.0; len];
+    let psf = Psf::Gaussian(Vec3f::new(scale, scale, scale));
+    psf.convolve(&mut field, size);
+    psf.convolve(&mut weight, size); // not darker near the borders
+    field.iter_mut().zip(weight).for_each(|(a, w)| *a /= w);
+
+    let mean = field.iter().sum::<f32>() / len as f32;
+    let std = (field.iter().map(|a| (a - mean).powi(2)).sum::<f32>() / len as f32).sqrt();
+    let std = if std > 0.0 { std } else { 1.0 };
+    field.into_iter().map(|a| (a - mean) / std).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{ImageStackRenderer, ObjectsScene};
+
+    fn noise() -> NoiseRenderer {
+        let mut scene = ObjectsScene::new();
+        scene.build_bvh();
+        NoiseRenderer::new(Box::new(ImageStackRenderer::new(Arc::new(scene))))
+    }
+
+    #[test]
+    fn attenuation() {
+        let mut noise = noise();
+        noise.set_attenuation(2.0);
+        let mut volume = vec![1.0; 8];
+        noise.apply(&mut volume, [2, 2, 2], &mut StdRng::seed_from_u64(0));
+        let e = f32::exp(-0.5);
+        assert_eq!(volume, [1.0, 1.0, 1.0, 1.0, e, e, e, e]);
+    }
+
+    #[test]
+    fn seeded_noise() {
+        let mut noise = noise();
+        noise.set_shot_noise(100.0);
+        noise.set_read_noise(0.01);
+        noise.set_background(0.1, 2.0);
+        noise.set_drift(0.01);
+        let size = [16, 16, 8];
+        let run = |seed| {
+            let mut volume = vec![0.5; 16 * 16 * 8];
+            noise.apply(&mut volume, size, &mut StdRng::seed_from_u64(seed));
+            volume
+        };
+        let a = run(1);
+        assert_eq!(a, run(1));
+        assert_ne!(a, run(2));
+        let mean = a.iter().sum::<f32>() / a.len() as f32;
+        assert!((mean - 0.6).abs() < 0.05, "mean {mean}");
+    }
+}
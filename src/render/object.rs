@@ -4,17 +4,44 @@ use std::sync::Arc;
 
 pub trait Object: Sync + Send {
     fn hit(&self, p: Vec3f) -> Option<Vec3f>;
+    /// Signed distance and label where `p` is inside the object.
+    fn label(&self, p: Vec3f) -> Option<(f32, u32)>;
+    fn signed_distance(&self, p: Vec3f) -> f32;
     fn bounding_box(&self) -> (Vec3f, Vec3f);
 }
 
+/// Label of the points of an object.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Label {
+    Constant(u32),
+    /// First label before the middle of the v axis, second after, e.g. the
+    /// two nodes of a segment.
+    VAxisSplit(u32, u32),
+}
+
+impl From<u32> for Label {
+    fn from(label: u32) -> Self {
+        Label::Constant(label)
+    }
+}
+
 pub struct SDFObject {
     sdf: Box<dyn SDF>,
     material: Arc<dyn Material>,
+    label: Label,
 }
 
 impl SDFObject {
-    pub fn new(sdf: Box<dyn SDF>, material: Arc<dyn Material>) -> Arc<SDFObject> {
-        Arc::new(SDFObject { sdf, material })
+    pub fn new(
+        sdf: Box<dyn SDF>,
+        material: Arc<dyn Material>,
+        label: impl Into<Label>,
+    ) -> Arc<SDFObject> {
+        Arc::new(SDFObject {
+            sdf,
+            material,
+            label: label.into(),
+        })
     }
 }
 
@@ -28,6 +55,24 @@ impl Object for SDFObject {
         }
     }
 
+    fn label(&self, p: Vec3f) -> Option<(f32, u32)> {
+        match self.label {
+            Label::Constant(label) => {
+                let signed_distance = self.sdf.signed_distance(p);
+                (signed_distance <= 0.0).then_some((signed_distance, label))
+            }
+            Label::VAxisSplit(a, b) => {
+                let hit = self.sdf.hit(p);
+                let label = if hit.v < 0.5 { a } else { b };
+                (hit.signed_distance <= 0.0).then_some((hit.signed_distance, label))
+            }
+        }
+    }
+
+    fn signed_distance(&self, p: Vec3f) -> f32 {
+        self.sdf.signed_distance(p)
+    }
+
     fn bounding_box(&self) -> (Vec3f, Vec3f) {
         self.sdf.bounding_box()
     }
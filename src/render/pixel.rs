@@ -0,0 +1,355 @@
+use image::{imageops, ImageBuffer, Luma, Primitive, Rgb};
+
+/// Type of the voxels of a rendered stack.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Depth {
+    U8,
+    U16,
+    U32,
+    F32,
+}
+
+impl TryFrom<&str> for Depth {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "u8" => Ok(Depth::U8),
+            "u16" => Ok(Depth::U16),
+            "u32" => Ok(Depth::U32),
+            "f32" => Ok(Depth::F32),
+            _ => Err("depth only support u8/u16/u32/f32"),
+        }
+    }
+}
+
+/// A slice of the rendered stack, in one of the supported depths.
+pub enum Image {
+    Gray8(ImageBuffer<Luma<u8>, Vec<u8>>),
+    Gray16(ImageBuffer<Luma<u16>, Vec<u16>>),
+    Gray32(ImageBuffer<Luma<u32>, Vec<u32>>),
+    Gray32Float(ImageBuffer<Luma<f32>, Vec<f32>>),
+    Rgb8(ImageBuffer<Rgb<u8>, Vec<u8>>),
+    Rgb16(ImageBuffer<Rgb<u16>, Vec<u16>>),
+    Rgb32(ImageBuffer<Rgb<u32>, Vec<u32>>),
+    Rgb32Float(ImageBuffer<Rgb<f32>, Vec<f32>>),
+    /// Gray images of the same size, one per channel.
+    Channels(Vec<Image>),
+}
+
+impl Image {
+    pub fn width(&self) -> u32 {
+        self.dimensions().0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+
+    /// Values of each channel, row by row.
+    pub fn channels(&self) -> Vec<Vec<f32>> {
+        match self {
+            Image::Gray8(img) => split(img.as_raw(), 1),
+            Image::Gray16(img) => split(img.as_raw(), 1),
+            Image::Gray32(img) => split(img.as_raw(), 1),
+            Image::Gray32Float(img) => split(img.as_raw(), 1),
+            Image::Rgb8(img) => split(img.as_raw(), 3),
+            Image::Rgb16(img) => split(img.as_raw(), 3),
+            Image::Rgb32(img) => split(img.as_raw(), 3),
+            Image::Rgb32Float(img) => split(img.as_raw(), 3),
+            Image::Channels(channels) => channels.iter().flat_map(|a| a.channels()).collect(),
+        }
+    }
+
+    /// Image of the same type and size holding the values of `channels`.
+    pub fn with_channels(&self, channels: Vec<Vec<f32>>) -> Image {
+        self.rebuild(&mut channels.into_iter())
+    }
+
+    /// Part of the image from `(x, y)` of `width` by `height` pixels, within
+    /// the image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+        let (x, y, w, h) = (x, y, width, height);
+        match self {
+            Image::Gray8(img) => Image::Gray8(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Gray16(img) => Image::Gray16(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Gray32(img) => Image::Gray32(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Gray32Float(img) => {
+                Image::Gray32Float(imageops::crop_imm(img, x, y, w, h).to_image())
+            }
+            Image::Rgb8(img) => Image::Rgb8(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Rgb16(img) => Image::Rgb16(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Rgb32(img) => Image::Rgb32(imageops::crop_imm(img, x, y, w, h).to_image()),
+            Image::Rgb32Float(img) => {
+                Image::Rgb32Float(imageops::crop_imm(img, x, y, w, h).to_image())
+            }
+            Image::Channels(channels) => {
+                Image::Channels(channels.iter().map(|a| a.crop(x, y, w, h)).collect())
+            }
+        }
+    }
+
+    /// Value of a full intensity, e.g. 255 in u8 or 1 in f32.
+    pub fn full_scale(&self) -> f32 {
+        match self {
+            Image::Gray8(_) | Image::Rgb8(_) => u8::from_intensity(1.0).value(),
+            Image::Gray16(_) | Image::Rgb16(_) => u16::from_intensity(1.0).value(),
+            Image::Gray32(_) | Image::Rgb32(_) => u32::from_intensity(1.0).value(),
+            Image::Gray32Float(_) | Image::Rgb32Float(_) => f32::from_intensity(1.0).value(),
+            Image::Channels(channels) => channels[0].full_scale(),
+        }
+    }
+
+    /// Image of `depth` holding the same intensities, rescaled from the full
+    /// scale of this image to that of `depth`.
+    pub fn to_depth(&self, depth: Depth) -> Image {
+        match depth {
+            Depth::U8 => self.convert::<u8>(),
+            Depth::U16 => self.convert::<u16>(),
+            Depth::U32 => self.convert::<u32>(),
+            Depth::F32 => self.convert::<f32>(),
+        }
+    }
+
+    fn convert<T: Channel>(&self) -> Image {
+        let (w, h) = self.dimensions();
+        let scale = T::from_intensity(1.0).value() / self.full_scale();
+        let channels = || {
+            let channels = self.channels().into_iter();
+            channels
+                .map(|a| a.into_iter().map(|v| v * scale).collect())
+                .collect()
+        };
+        match self {
+            Image::Gray8(_) | Image::Gray16(_) | Image::Gray32(_) | Image::Gray32Float(_) => {
+                T::gray(w, h, merge(channels()))
+            }
+            Image::Rgb8(_) | Image::Rgb16(_) | Image::Rgb32(_) | Image::Rgb32Float(_) => {
+                T::rgb(w, h, merge(channels()))
+            }
+            Image::Channels(images) => {
+                Image::Channels(images.iter().map(|a| a.convert::<T>()).collect())
+            }
+        }
+    }
+
+    fn rebuild(&self, channels: &mut impl Iterator<Item = Vec<f32>>) -> Image {
+        let (w, h) = self.dimensions();
+        let mut take = |n| channels.take(n).collect();
+        match self {
+            Image::Gray8(_) => u8::gray(w, h, merge(take(1))),
+            Image::Gray16(_) => u16::gray(w, h, merge(take(1))),
+            Image::Gray32(_) => u32::gray(w, h, merge(take(1))),
+            Image::Gray32Float(_) => f32::gray(w, h, merge(take(1))),
+            Image::Rgb8(_) => u8::rgb(w, h, merge(take(3))),
+            Image::Rgb16(_) => u16::rgb(w, h, merge(take(3))),
+            Image::Rgb32(_) => u32::rgb(w, h, merge(take(3))),
+            Image::Rgb32Float(_) => f32::rgb(w, h, merge(take(3))),
+            Image::Channels(images) => {
+                Image::Channels(images.iter().map(|a| a.rebuild(channels)).collect())
+            }
+        }
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Image::Gray8(img) => img.dimensions(),
+            Image::Gray16(img) => img.dimensions(),
+            Image::Gray32(img) => img.dimensions(),
+            Image::Gray32Float(img) => img.dimensions(),
+            Image::Rgb8(img) => img.dimensions(),
+            Image::Rgb16(img) => img.dimensions(),
+            Image::Rgb32(img) => img.dimensions(),
+            Image::Rgb32Float(img) => img.dimensions(),
+            Image::Channels(channels) => channels[0].dimensions(),
+        }
+    }
+}
+
+/// Value of a voxel. Intensities in [0, 1] span the whole range of integer
+/// types and are kept as is in floats, while labels saturate.
+pub trait Channel: Primitive + Send + 'static {
+    fn from_intensity(v: f32) -> Self;
+    fn from_label(label: u32) -> Self;
+    /// Value as is, e.g. a distance, rounded and saturated in integers.
+    fn from_value(v: f32) -> Self;
+    fn value(self) -> f32;
+    fn gray(width: u32, height: u32, raw: Vec<Self>) -> Image;
+    fn rgb(width: u32, height: u32, raw: Vec<Self>) -> Image;
+}
+
+macro_rules! impl_image {
+    ($gray:ident, $rgb:ident) => {
+        fn gray(width: u32, height: u32, raw: Vec<Self>) -> Image {
+            Image::$gray(ImageBuffer::from_raw(width, height, raw).unwrap())
+        }
+
+        fn rgb(width: u32, height: u32, raw: Vec<Self>) -> Image {
+            Image::$rgb(ImageBuffer::from_raw(width, height, raw).unwrap())
+        }
+    };
+}
+
+macro_rules! impl_integer_channel {
+    ($t:ty, $gray:ident, $rgb:ident) => {
+        impl Channel for $t {
+            fn from_intensity(v: f32) -> Self {
+                f64::round(<$t>::MAX as f64 * v.clamp(0.0, 1.0) as f64) as $t
+            }
+
+            fn from_label(label: u32) -> Self {
+                <$t>::try_from(label).unwrap_or(<$t>::MAX)
+            }
+
+            fn from_value(v: f32) -> Self {
+                v.round() as $t
+            }
+
+            fn value(self) -> f32 {
+                self as f32
+            }
+
+            impl_image!($gray, $rgb);
+        }
+    };
+}
+
+impl_integer_channel!(u8, Gray8, Rgb8);
+impl_integer_channel!(u16, Gray16, Rgb16);
+impl_integer_channel!(u32, Gray32, Rgb32);
+
+impl Channel for f32 {
+    fn from_intensity(v: f32) -> Self {
+        v
+    }
+
+    fn from_label(label: u32) -> Self {
+        label as f32
+    }
+
+    fn from_value(v: f32) -> Self {
+        v
+    }
+
+    fn value(self) -> f32 {
+        self
+    }
+
+    impl_image!(Gray32Float, Rgb32Float);
+}
+
+/// Stage processing a whole stack at once: `f` gets the volume of each
+/// channel, slice by slice, and their size along x, y and z. The result is
+/// converted to `depth`, or kept of the type of `stack`.
+pub fn map_volumes<F>(stack: &[Image], depth: Option<Depth>, f: F) -> Vec<Image>
+where
+    F: FnOnce(&mut [Vec<f32>], [usize; 3]),
+{
+    let (size, mut volumes) = to_volumes(stack);
+    f(&mut volumes, size);
+    let images = from_volumes(stack, &volumes).into_iter();
+    match depth {
+        Some(depth) => images.map(|a| a.to_depth(depth)).collect(),
+        None => images.collect(),
+    }
+}
+
+/// Size along x, y and z of a stack, and the volume of each of its channels,
+/// slice by slice.
+fn to_volumes(stack: &[Image]) -> ([usize; 3], Vec<Vec<f32>>) {
+    let Some(first) = stack.first() else {
+        return ([0, 0, 0], vec![]);
+    };
+
+    let (w, h) = (first.width() as usize, first.height() as usize);
+    let size = [w, h, stack.len()];
+    let mut volumes = vec![Vec::with_capacity(w * h * stack.len()); first.channels().len()];
+    for img in stack.iter() {
+        for (volume, channel) in volumes.iter_mut().zip(img.channels()) {
+            volume.extend(channel);
+        }
+    }
+    (size, volumes)
+}
+
+/// Stack of the same type and size as `stack` holding `volumes`.
+fn from_volumes(stack: &[Image], volumes: &[Vec<f32>]) -> Vec<Image> {
+    let images = stack.iter().enumerate().map(|(z, img)| {
+        let len = (img.width() * img.height()) as usize;
+        let slice = z * len..(z + 1) * len;
+        img.with_channels(volumes.iter().map(|a| a[slice.clone()].to_vec()).collect())
+    });
+    images.collect()
+}
+
+/// Deinterleave `n` channels.
+fn split<T: Channel>(raw: &[T], n: usize) -> Vec<Vec<f32>> {
+    let channel = |c| raw.iter().skip(c).step_by(n).map(|a| a.value()).collect();
+    (0..n).map(channel).collect()
+}
+
+/// Interleave channels, converted as values.
+fn merge<T: Channel>(channels: Vec<Vec<f32>>) -> Vec<T> {
+    let n = channels.len();
+    let len = channels.first().map_or(0, |a| a.len());
+    let value = |i: usize| T::from_value(channels[i % n][i / n]);
+    (0..n * len).map(value).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_conversions() {
+        assert_eq!(u8::from_intensity(0.5), 128);
+        assert_eq!(u16::from_intensity(1.5), u16::MAX);
+        assert_eq!(u32::from_intensity(1.0), u32::MAX);
+        assert_eq!(f32::from_intensity(0.25), 0.25);
+        assert_eq!(u8::from_label(300), u8::MAX);
+        assert_eq!(u16::from_label(300), 300);
+    }
+
+    #[test]
+    fn channels() {
+        let raw = vec![1u16, 2, 3, 4, 5, 6];
+        let img = u16::rgb(2, 1, raw.clone());
+        let channels = img.channels();
+        assert_eq!(
+            channels,
+            vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]
+        );
+        let Image::Rgb16(img) = img.with_channels(channels) else {
+            panic!("not a rgb image");
+        };
+        assert_eq!(img.into_raw(), raw);
+
+        let img = Image::Channels(vec![u8::gray(1, 1, vec![7]), u8::gray(1, 1, vec![9])]);
+        let Image::Channels(images) = img.with_channels(vec![vec![300.0], vec![-2.0]]) else {
+            panic!("not a multi-channel image");
+        };
+        let [Image::Gray8(a), Image::Gray8(b)] = &images[..] else {
+            panic!("not two gray channels");
+        };
+        assert_eq!((a.as_raw()[0], b.as_raw()[0]), (255, 0));
+    }
+
+    #[test]
+    fn depth_conversions() {
+        let img = f32::rgb(2, 1, vec![0.0, 0.5, 1.0, 1.5, -0.5, 0.25]);
+        let Image::Rgb8(img) = img.to_depth(Depth::U8) else {
+            panic!("not a rgb image");
+        };
+        assert_eq!(img.as_raw(), &[0, 128, 255, 255, 0, 64]);
+
+        let img = Image::Channels(vec![u8::gray(1, 1, vec![255]), u8::gray(1, 1, vec![51])]);
+        let Image::Channels(images) = img.to_depth(Depth::F32) else {
+            panic!("not a multi-channel image");
+        };
+        let [Image::Gray32Float(a), Image::Gray32Float(b)] = &images[..] else {
+            panic!("not two float channels");
+        };
+        assert_eq!(a.as_raw()[0], 1.0);
+        assert!((b.as_raw()[0] - 0.2).abs() < 1e-6);
+    }
+}
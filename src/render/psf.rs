@@ -0,0 +1,226 @@
+use super::{
+    pixel::{map_volumes, Depth},
+    renderer::{Images, Renderer},
+    tiff::read_stack,
+    Scene,
+};
+use crate::vec::Vec3f;
+use rustfft::{num_complex::Complex32, FftPlanner};
+use std::{error::Error, sync::Arc};
+
+/// Point spread function of a microscope, in voxels of the rendered stack.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Psf {
+    /// Anisotropic Gaussian, by its standard deviations along x, y and z,
+    /// convolved axis by axis.
+    Gaussian(Vec3f),
+    /// Sampled PSF centred in a stack, values slice by slice, convolved by
+    /// FFT.
+    Stack { size: [usize; 3], values: Vec<f32> },
+}
+
+impl Psf {
+    /// Read a PSF stack from a gray tiff, normalized to a unit sum so that
+    /// the total intensity is kept.
+    pub fn read(fname: &str) -> Result<Psf, Box<dyn Error>> {
+        let (size, mut values) = read_stack(fname)?;
+        let sum: f32 = values.iter().sum();
+        if sum <= 0.0 {
+            return Err("psf sums to zero".into());
+        }
+        values.iter_mut().for_each(|v| *v /= sum);
+        Ok(Psf::Stack { size, values })
+    }
+
+    /// Convolve a volume of `size` voxels, slice by slice, taking zeros out
+    /// of it.
+    pub fn convolve(&self, volume: &mut [f32], size: [usize; 3]) {
+        match self {
+            Psf::Gaussian(sigma) => {
+                for (axis, sigma) in [sigma.x, sigma.y, sigma.z].into_iter().enumerate() {
+                    convolve_axis(volume, size, axis, &gaussian(sigma));
+                }
+            }
+            Psf::Stack { size: k, values } => convolve_fft(volume, size, values, *k),
+        }
+    }
+}
+
+/// Post-render stage blurring the stack of a renderer by a PSF, e.g. to turn
+/// a mask into a synthetic microscopy image aligned with it. The whole stack
+/// is kept in memory, and each channel is convolved on its own. Integer
+/// stacks are blurred as already quantized, so render in f32 and set the
+/// output depth here to quantize once.
+pub struct PsfRenderer {
+    renderer: Box<dyn Renderer>,
+    psf: Psf,
+    depth: Option<Depth>,
+}
+
+impl PsfRenderer {
+    pub fn new(renderer: Box<dyn Renderer>, psf: Psf) -> PsfRenderer {
+        PsfRenderer {
+            renderer,
+            psf,
+            depth: None,
+        }
+    }
+
+    /// Depth of the output, that of the rendered stack by default.
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = Some(depth);
+    }
+}
+
+impl Renderer for PsfRenderer {
+    fn scene(&self) -> Arc<dyn Scene> {
+        self.renderer.scene()
+    }
+
+    fn image_stack<'a>(&'a self) -> Images<'a> {
+        let stack: Vec<_> = self.renderer.image_stack().collect();
+        let stack = map_volumes(&stack, self.depth, |volumes, size| {
+            for volume in volumes.iter_mut() {
+                self.psf.convolve(volume, size);
+            }
+        });
+        Box::new(stack.into_iter())
+    }
+}
+
+/// Normalized Gaussian kernel, truncated at 3 standard deviations.
+fn gaussian(sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 {
+        return vec![1.0];
+    }
+
+    let radius = f32::ceil(3.0 * sigma) as i32;
+    let kernel: Vec<_> = (-radius..=radius)
+        .map(|i| f32::exp(-0.5 * (i as f32 / sigma).powi(2)))
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|a| a / sum).collect()
+}
+
+fn strides(size: [usize; 3]) -> [usize; 3] {
+    [1, size[0], size[0] * size[1]]
+}
+
+/// First index of each line of the volume along `axis`.
+fn lines(size: [usize; 3], axis: usize) -> impl Iterator<Item = usize> {
+    let (stride, n) = (strides(size)[axis], size[axis]);
+    (0..size.iter().product()).filter(move |i| i / stride % n == 0)
+}
+
+/// Convolve along an axis with a centred kernel.
+fn convolve_axis(volume: &mut [f32], size: [usize; 3], axis: usize, kernel: &[f32]) {
+    if kernel.len() == 1 {
+        return;
+    }
+
+    let (stride, n) = (strides(size)[axis], size[axis]);
+    let radius = (kernel.len() / 2) as isize;
+    let mut line = vec![0.0; n];
+    for start in lines(size, axis) {
+        for (i, v) in line.iter_mut().enumerate() {
+            *v = volume[start + i * stride];
+        }
+        for i in 0..n {
+            let value = kernel.iter().enumerate().fold(0.0, |acc, (k, w)| {
+                let j = i as isize + k as isize - radius;
+                match line.get(j as usize) {
+                    Some(v) if j >= 0 => acc + w * v,
+                    _ => acc,
+                }
+            });
+            volume[start + i * stride] = value;
+        }
+    }
+}
+
+/// Linear convolution by FFT, padding the volume so it does not wrap around.
+fn convolve_fft(volume: &mut [f32], size: [usize; 3], kernel: &[f32], ksize: [usize; 3]) {
+    let padded = [0, 1, 2].map(|a| size[a] + ksize[a] - 1);
+    let mut planner = FftPlanner::new();
+    let spectrum = |values: &[f32], size: [usize; 3], planner: &mut FftPlanner<f32>| {
+        let mut data = vec![Complex32::default(); padded.iter().product()];
+        let (s, p) = (strides(size), strides(padded));
+        for (i, v) in values.iter().enumerate() {
+            let [x, y, z] = [0, 1, 2].map(|a| i / s[a] % size[a]);
+            data[x + y * p[1] + z * p[2]] = Complex32::new(*v, 0.0);
+        }
+        fft(&mut data, padded, false, planner);
+        data
+    };
+
+    let mut data = spectrum(volume, size, &mut planner);
+    let k = spectrum(kernel, ksize, &mut planner);
+    data.iter_mut().zip(k).for_each(|(a, b)| *a *= b);
+    fft(&mut data, padded, true, &mut planner);
+
+    let scale = 1.0 / data.len() as f32;
+    let (s, p) = (strides(size), strides(padded));
+    for (i, v) in volume.iter_mut().enumerate() {
+        // shifted by the centre of the kernel
+        let [x, y, z] = [0, 1, 2].map(|a| i / s[a] % size[a] + ksize[a] / 2);
+        *v = data[x + y * p[1] + z * p[2]].re * scale;
+    }
+}
+
+/// Unnormalized 3D FFT, as 1D FFTs along each axis.
+fn fft(data: &mut [Complex32], size: [usize; 3], inverse: bool, planner: &mut FftPlanner<f32>) {
+    for axis in 0..3 {
+        let (stride, n) = (strides(size)[axis], size[axis]);
+        let fft = match inverse {
+            true => planner.plan_fft_inverse(n),
+            false => planner.plan_fft_forward(n),
+        };
+        let mut line = vec![Complex32::default(); n];
+        for start in lines(size, axis) {
+            for (i, v) in line.iter_mut().enumerate() {
+                *v = data[start + i * stride];
+            }
+            fft.process(&mut line);
+            for (i, v) in line.iter().enumerate() {
+                data[start + i * stride] = *v;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separable_and_fft_agree() {
+        let size = [9, 8, 7];
+        let mut volume = vec![0.0; size.iter().product()];
+        volume[4 + 3 * 9 + 3 * 72] = 1.0;
+        volume[1 + 6 * 9 + 5 * 72] = 2.0;
+
+        let sigma = Vec3f::new(1.0, 0.5, 1.5);
+        let mut separable = volume.clone();
+        Psf::Gaussian(sigma).convolve(&mut separable, size);
+        let total: f32 = separable.iter().sum();
+        assert!(total > 2.5 && total < 3.0); // lost out of the volume
+
+        let [kx, ky, kz] = [sigma.x, sigma.y, sigma.z].map(gaussian);
+        let ksize = [kx.len(), ky.len(), kz.len()];
+        let mut values = vec![];
+        for z in kz.iter() {
+            for y in ky.iter() {
+                values.extend(kx.iter().map(|x| x * y * z));
+            }
+        }
+        let mut fft = volume.clone();
+        Psf::Stack {
+            size: ksize,
+            values,
+        }
+        .convolve(&mut fft, size);
+        for (a, b) in separable.iter().zip(fft.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+}
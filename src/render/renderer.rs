@@ -1,10 +1,15 @@
-use super::{anti_aliasing::MSAA_OPTIONS, Msaa, Scene};
+use super::{
+    anti_aliasing::PartialVolume,
+    pixel::{Channel, Depth, Image},
+    Coverage, Msaa, Scene,
+};
 use crate::vec::Vec3f;
-use image::{ImageBuffer, Luma};
-use std::sync::{mpsc, Arc};
+use std::{
+    iter,
+    sync::{mpsc, Arc},
+};
 use threadpool::ThreadPool;
 
-pub type Image = image::ImageBuffer<Luma<u8>, Vec<u8>>;
 pub type Images<'a> = Box<dyn ExactSizeIterator<Item = Image> + 'a>;
 
 pub trait Renderer {
@@ -12,11 +17,50 @@ pub trait Renderer {
     fn image_stack(&self) -> Images;
 }
 
+/// What a voxel of the rendered stack holds.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RenderMode {
+    /// Luma of the material colour, averaged over samples.
+    Intensity,
+    /// Material colour averaged over samples, as RGB.
+    Rgb,
+    /// Material colour averaged over samples, a channel per component.
+    ColorChannels,
+    /// Label of the object hit, e.g. the structure type, taking the most
+    /// frequent label over samples, or the smallest one on ties.
+    Label,
+    /// Coverage of each label, i.e. the fraction of samples taking it, a
+    /// channel per label.
+    LabelChannels(Vec<u32>),
+    /// Signed distance to the scene, negative inside, or its absolute value
+    /// when unsigned, averaged over samples and truncated to
+    /// `[-truncation, truncation]`. Rounded and saturated in integer depths,
+    /// so negative distances become 0.
+    Distance {
+        signed: bool,
+        truncation: Option<f32>,
+    },
+}
+
+impl RenderMode {
+    /// Number of values per voxel.
+    fn len(&self) -> usize {
+        match self {
+            RenderMode::Intensity | RenderMode::Label | RenderMode::Distance { .. } => 1,
+            RenderMode::Rgb | RenderMode::ColorChannels => 3,
+            RenderMode::LabelChannels(labels) => labels.len(),
+        }
+    }
+}
+
 pub struct ImageStackRenderer {
     scene: Arc<dyn Scene>,
+    mode: RenderMode,
+    depth: Depth,
     resolution: Vec3f, // voxel per um
     range: Option<(Vec3f, Vec3f)>,
     msaa: Msaa,
+    coverage: Coverage,
     num_threads: Option<usize>,
 }
 
@@ -24,13 +68,24 @@ impl ImageStackRenderer {
     pub fn new(scene: Arc<dyn Scene>) -> ImageStackRenderer {
         ImageStackRenderer {
             scene,
+            mode: RenderMode::Intensity,
+            depth: Depth::U8,
             resolution: Vec3f::new(1.0, 1.0, 1.0),
             range: None,
-            msaa: Msaa::Disable,
+            msaa: Msaa::default(),
+            coverage: Coverage::Samples,
             num_threads: None,
         }
     }
 
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = depth;
+    }
+
     pub fn set_resolution(&mut self, x: f32, y: f32, z: f32) {
         self.resolution = Vec3f::new(x, y, z);
     }
@@ -43,6 +98,10 @@ impl ImageStackRenderer {
         self.msaa = msaa;
     }
 
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = coverage;
+    }
+
     pub fn set_num_threads(&mut self, num_threads: usize) {
         self.num_threads = Some(num_threads);
     }
@@ -69,11 +128,9 @@ impl Renderer for ImageStackRenderer {
         Box::new(ImageStackRendererIterator {
             renderer: self,
             min,
-            max,
             width: f32::ceil((max.x - min.x) / self.resolution.x) as u32,
             height: f32::ceil((max.y - min.y) / self.resolution.y) as u32,
             frames: f32::ceil((max.z - min.z) / self.resolution.z) as u32,
-            msaa: MSAA_OPTIONS.get(&self.msaa).unwrap(),
             pool: ThreadPool::new(self.num_threads()),
             i: 0,
         })
@@ -83,24 +140,36 @@ impl Renderer for ImageStackRenderer {
 pub struct ImageStackRendererIterator<'a> {
     renderer: &'a ImageStackRenderer,
     min: Vec3f,
-    max: Vec3f,
     width: u32,
     height: u32,
     frames: u32,
-    msaa: &'static [Vec3f],
     pool: ThreadPool,
     i: u32,
 }
 
 impl Iterator for ImageStackRendererIterator<'_> {
-    type Item = image::ImageBuffer<Luma<u8>, Vec<u8>>;
+    type Item = Image;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.i >= self.frames {
             return None;
         }
 
-        let msaa = self.msaa;
+        let img = match self.renderer.depth {
+            Depth::U8 => self.render::<u8>(),
+            Depth::U16 => self.render::<u16>(),
+            Depth::U32 => self.render::<u32>(),
+            Depth::F32 => self.render::<f32>(),
+        };
+        self.i += 1;
+        Some(img)
+    }
+}
+
+impl ImageStackRendererIterator<'_> {
+    fn render<T: Channel>(&self) -> Image {
+        let mode = &self.renderer.mode;
+        let coverage = self.renderer.coverage;
         let (w, h) = (self.width, self.height);
         let mx = self.min.x;
         let r = self.renderer.resolution;
@@ -117,38 +186,240 @@ impl Iterator for ImageStackRendererIterator<'_> {
 
             let tx = tx.clone();
             let scene = Arc::clone(&self.renderer.scene);
+            let mode = mode.clone();
+            let msaa = self.renderer.msaa.clone();
+            let (row, slice) = (h_per_task * i, self.i);
             self.pool.execute(move || {
-                let img = ImageBuffer::from_fn(w, h, |x, y| {
-                    let p = Vec3f::new(mx + r.x * x as f32, my + r.y * (h - y) as f32, z);
-                    let luma = msaa
-                        .as_ref()
-                        .into_iter()
-                        .fold(0.0, |acc, v| acc + to_luma(scene.hit(p + r * v)));
-                    Luma([f32::round(255.0 * luma / msaa.len() as f32) as u8])
-                });
-                tx.send((i, img)).unwrap();
+                let mut raw = Vec::with_capacity((w * h) as usize * mode.len());
+                let (diagonal, weight) = (r.norm(), 1.0 / msaa.len() as f32);
+                for y in 0..h {
+                    for x in 0..w {
+                        let p = Vec3f::new(mx + r.x * x as f32, my + r.y * (h - y) as f32, z);
+                        let c = p + r * 0.5;
+                        match (coverage, &mode) {
+                            (Coverage::Adaptive, _)
+                                if scene.signed_distance(c, diagonal).abs() >= diagonal =>
+                            {
+                                shade(scene.as_ref(), &mode, iter::once((c, 1.0)), &mut raw)
+                            }
+                            (Coverage::Samples | Coverage::Adaptive, _) => {
+                                let index = [x, row + y, slice];
+                                let samples = msaa.points(index).map(|v| (p + r * v, weight));
+                                shade(scene.as_ref(), &mode, samples, &mut raw);
+                            }
+                            // distances are smooth already
+                            (Coverage::Analytic, RenderMode::Distance { .. }) => {
+                                shade(scene.as_ref(), &mode, iter::once((c, 1.0)), &mut raw)
+                            }
+                            (Coverage::Analytic, _) => {
+                                let pv = PartialVolume::new(scene.as_ref(), c, r);
+                                shade(scene.as_ref(), &mode, pv.samples().into_iter(), &mut raw);
+                            }
+                        }
+                    }
+                }
+                tx.send((i, raw)).unwrap();
             });
         }
-        self.i += 1;
         drop(tx);
 
         let mut parts: Vec<_> = rx.into_iter().collect();
         parts.sort_by(|a, b| a.0.cmp(&b.0));
-        let img = parts
-            .into_iter()
-            .map(|a| a.1.into_raw())
-            .collect::<Vec<_>>()
-            .concat();
-        ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(self.width, self.height, img)
+        let raw = parts.into_iter().flat_map(|a| a.1).collect::<Vec<_>>();
+        match mode {
+            RenderMode::Intensity | RenderMode::Label | RenderMode::Distance { .. } => {
+                T::gray(w, h, raw)
+            }
+            RenderMode::Rgb => T::rgb(w, h, raw),
+            RenderMode::ColorChannels | RenderMode::LabelChannels(_) => {
+                let n = mode.len();
+                let channels = (0..n).map(|c| {
+                    let channel = raw.iter().skip(c).step_by(n).copied().collect();
+                    T::gray(w, h, channel)
+                });
+                Image::Channels(channels.collect())
+            }
+        }
+    }
+}
+
+/// Push the values of a voxel given its sample points, weighted by the
+/// fraction of the voxel they stand for.
+fn shade<T: Channel>(
+    scene: &dyn Scene,
+    mode: &RenderMode,
+    samples: impl Iterator<Item = (Vec3f, f32)>,
+    out: &mut Vec<T>,
+) {
+    match mode {
+        RenderMode::Intensity => {
+            let luma = samples.fold(0.0, |acc, (p, w)| acc + w * to_luma(scene.hit(p)));
+            out.push(T::from_intensity(luma));
+        }
+        RenderMode::Rgb | RenderMode::ColorChannels => {
+            let zero = Vec3f::new(0.0, 0.0, 0.0);
+            let c = samples.fold(zero, |acc, (p, w)| acc + scene.hit(p) * w);
+            out.extend([c.x, c.y, c.z].map(T::from_intensity));
+        }
+        RenderMode::Label => {
+            let labels = samples.map(|(p, w)| (scene.label(p), w));
+            out.push(T::from_label(majority(labels)))
+        }
+        RenderMode::LabelChannels(labels) => {
+            let hits: Vec<_> = samples.map(|(p, w)| (scene.label(p), w)).collect();
+            for label in labels {
+                let fraction = hits.iter().filter(|a| a.0 == *label).map(|a| a.1).sum();
+                out.push(T::from_intensity(fraction));
+            }
+        }
+        RenderMode::Distance { signed, truncation } => {
+            let max = truncation.unwrap_or(f32::INFINITY);
+            let d = samples
+                .map(|(p, w)| w * scene.signed_distance(p, max))
+                .sum::<f32>();
+            let d = if *signed { d } else { d.abs() };
+            out.push(T::from_value(d.clamp(-max, max)));
+        }
     }
 }
 
 impl ExactSizeIterator for ImageStackRendererIterator<'_> {
     fn len(&self) -> usize {
-        f32::ceil((self.max.z - self.min.z) / self.renderer.resolution.y) as usize
+        (self.frames - self.i) as usize
+    }
+}
+
+/// Label of the largest weight, the smallest one on ties.
+fn majority(labels: impl Iterator<Item = (u32, f32)>) -> u32 {
+    let mut labels: Vec<_> = labels.collect();
+    labels.sort_unstable_by_key(|a| a.0);
+    let mut best = (0.0, 0); // (weight, label)
+    for run in labels.chunk_by(|a, b| a.0 == b.0) {
+        let weight = run.iter().map(|a| a.1).sum();
+        if weight > best.0 {
+            best = (weight, run[0].0);
+        }
     }
+    best.1
 }
 
 fn to_luma(c: Vec3f) -> f32 {
     0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{ObjectsScene, Pattern, SDFObject, SolidColor},
+        sdf::Sphere,
+    };
+
+    fn renderer(mode: RenderMode) -> ImageStackRenderer {
+        let mut scene = ObjectsScene::new();
+        let red = SolidColor::new(Vec3f::new(1.0, 0.0, 0.0));
+        let sphere = Box::new(Sphere::new(Vec3f::new(0.0, 0.0, 0.0), 2.0));
+        scene.add(SDFObject::new(sphere, red, 3));
+        scene.build_bvh();
+
+        let mut renderer = ImageStackRenderer::new(Arc::new(scene));
+        renderer.set_range(Vec3f::new(-2.0, -2.0, -0.5), Vec3f::new(2.0, 2.0, 0.5));
+        renderer.set_num_threads(1);
+        renderer.set_mode(mode);
+        renderer
+    }
+
+    #[test]
+    fn rgb() {
+        let renderer = renderer(RenderMode::Rgb);
+        let Some(Image::Rgb8(img)) = renderer.image_stack().next() else {
+            panic!("not a rgb image");
+        };
+        assert!(img.pixels().any(|p| p.0 == [255, 0, 0]));
+        assert!(img.pixels().all(|p| p.0[1] == 0 && p.0[2] == 0));
+    }
+
+    #[test]
+    fn adaptive_coverage() {
+        // voxels far enough from the surface of the sphere skip supersampling
+        let (radius, extent) = (4.0, 6.0);
+        let mut scene = ObjectsScene::new();
+        let sphere = Box::new(Sphere::new(Vec3f::new(0.0, 0.0, 0.0), radius));
+        scene.add(SDFObject::new(
+            sphere,
+            SolidColor::new(Vec3f::new(1.0, 1.0, 1.0)),
+            1,
+        ));
+        scene.build_bvh();
+        let scene: Arc<dyn Scene> = Arc::new(scene);
+
+        let render = |coverage| {
+            let mut renderer = ImageStackRenderer::new(scene.clone());
+            renderer.set_range(
+                Vec3f::new(-extent, -extent, -0.5),
+                Vec3f::new(extent, extent, 0.5),
+            );
+            renderer.set_num_threads(1);
+            renderer.set_msaa(Msaa::new(8, Pattern::Grid).unwrap());
+            renderer.set_coverage(coverage);
+            let Some(Image::Gray8(img)) = renderer.image_stack().next() else {
+                panic!("not a gray image");
+            };
+            img
+        };
+        let samples = render(Coverage::Samples);
+        let adaptive = render(Coverage::Adaptive);
+        assert!(samples.pixels().any(|p| p.0[0] > 0 && p.0[0] < 255));
+        assert_eq!(samples, adaptive);
+
+        let diagonal = f32::sqrt(3.0);
+        let (mut inside, mut outside) = (0, 0);
+        for (x, y, p) in adaptive.enumerate_pixels() {
+            let c = Vec3f::new(x as f32 - extent + 0.5, extent - y as f32 + 0.5, 0.0);
+            let sd = c.norm() - radius;
+            if sd <= -diagonal {
+                assert_eq!(p.0, [255]);
+                inside += 1;
+            } else if sd >= diagonal {
+                assert_eq!(p.0, [0]);
+                outside += 1;
+            }
+        }
+        assert!(inside > 0 && outside > 0);
+    }
+
+    #[test]
+    fn integer_distance() {
+        for signed in [true, false] {
+            let render = |depth| {
+                let mut renderer = renderer(RenderMode::Distance {
+                    signed,
+                    truncation: None,
+                });
+                renderer.set_depth(depth);
+                let img = renderer.image_stack().next().unwrap();
+                img.channels().remove(0)
+            };
+            let rounded: Vec<_> = render(Depth::F32)
+                .into_iter()
+                .map(|d| d.round().max(0.0))
+                .collect();
+            assert!(rounded.iter().any(|&d| d > 0.0));
+            assert_eq!(render(Depth::U8), rounded);
+        }
+    }
+
+    #[test]
+    fn label_channels() {
+        let renderer = renderer(RenderMode::LabelChannels(vec![3, 4]));
+        let stack: Vec<_> = renderer.image_stack().collect();
+        let [Image::Channels(channels)] = &stack[..] else {
+            panic!("not a multi-channel image");
+        };
+        let [Image::Gray8(a), Image::Gray8(b)] = &channels[..] else {
+            panic!("not two gray channels");
+        };
+        assert!(a.pixels().any(|p| p.0 == [255]));
+        assert!(b.pixels().all(|p| p.0 == [0]));
+    }
+}
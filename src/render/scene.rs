@@ -7,13 +7,53 @@ use std::sync::Arc;
 
 pub trait Scene: Send + Sync {
     fn hit(&self, p: Vec3f) -> Vec3f;
+    /// Label of the object at `p`, 0 for background.
+    fn label(&self, p: Vec3f) -> u32;
+    /// Signed distance to the nearest object, i.e. the minimum over objects,
+    /// capped by `max`. Inside overlaps, it is only a bound of the depth.
+    fn signed_distance(&self, p: Vec3f, max: f32) -> f32;
     fn bounding_box(&self) -> Option<(Vec3f, Vec3f)>;
     fn set_background(&mut self, background: Vec3f);
 }
 
+/// Label taken where objects of different labels overlap.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Overlap {
+    /// The object added first to the scene wins.
+    First,
+    /// The object `p` lies deepest in wins, i.e. the one with the smallest
+    /// signed distance, then the smallest label.
+    Nearest,
+    /// Overlaps take a label of their own.
+    Label(u32),
+}
+
+impl Overlap {
+    /// Resolve `(object index, (signed distance, label))` of the objects
+    /// containing a point.
+    pub fn resolve(self, labels: impl Iterator<Item = (usize, (f32, u32))>) -> Option<u32> {
+        match self {
+            Overlap::First => labels.min_by_key(|(i, _)| *i).map(|(_, (_, l))| l),
+            Overlap::Nearest => labels
+                .map(|(_, a)| a)
+                .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+                .map(|(_, l)| l),
+            Overlap::Label(overlap) => {
+                let mut labels = labels.map(|(_, (_, l))| l);
+                let first = labels.next()?;
+                Some(match labels.all(|l| l == first) {
+                    true => first,
+                    false => overlap,
+                })
+            }
+        }
+    }
+}
+
 pub struct ObjectsScene {
     objects: Option<Vec<Arc<dyn Object>>>,
     background: Vec3f,
+    overlap: Overlap,
     acceletor: Option<Box<dyn Accelerator>>,
 }
 
@@ -22,6 +62,7 @@ impl ObjectsScene {
         ObjectsScene {
             objects: Some(Vec::new()),
             background: Vec3f::new(0.0, 0.0, 0.0),
+            overlap: Overlap::Nearest,
             acceletor: None,
         }
     }
@@ -33,6 +74,10 @@ impl ObjectsScene {
         }
     }
 
+    pub fn set_overlap(&mut self, overlap: Overlap) {
+        self.overlap = overlap;
+    }
+
     pub fn build_bvh(&mut self) {
         match self.objects.take() {
             Some(objs) => self.acceletor = Some(BVH::new(objs)),
@@ -55,6 +100,30 @@ impl Scene for ObjectsScene {
         hit.unwrap_or(self.background)
     }
 
+    fn label(&self, p: Vec3f) -> u32 {
+        let label = match (&self.acceletor, &self.objects) {
+            (Some(acc), _) => acc.label(p, self.overlap),
+            (None, Some(objs)) => {
+                let labels = objs.iter().enumerate();
+                self.overlap
+                    .resolve(labels.filter_map(|(i, obj)| Some((i, obj.label(p)?))))
+            }
+            (None, None) => panic!("unexpect mode"),
+        };
+        label.unwrap_or(0)
+    }
+
+    fn signed_distance(&self, p: Vec3f, max: f32) -> f32 {
+        match (&self.acceletor, &self.objects) {
+            (Some(acc), _) => acc.signed_distance(p, max),
+            (None, Some(objs)) => objs
+                .iter()
+                .map(|obj| obj.signed_distance(p))
+                .fold(max, f32::min),
+            (None, None) => panic!("unexpect mode"),
+        }
+    }
+
     fn bounding_box(&self) -> Option<(Vec3f, Vec3f)> {
         match (&self.acceletor, &self.objects) {
             (Some(acc), _) => acc.bounding_box(),
@@ -69,3 +138,56 @@ impl Scene for ObjectsScene {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::SDFObject, render::SolidColor, sdf::Sphere};
+
+    #[test]
+    fn label_deepest_object() {
+        let mut scene = ObjectsScene::new();
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        let sphere = |x: f32, r: f32| Box::new(Sphere::new(Vec3f::new(x, 0.0, 0.0), r));
+        scene.add(SDFObject::new(sphere(0.0, 5.0), white.clone(), 1));
+        scene.add(SDFObject::new(sphere(6.0, 2.0), white.clone(), 3));
+        scene.add(SDFObject::new(sphere(6.0, 2.0), white, 2));
+        scene.build_bvh();
+
+        assert_eq!(scene.label(Vec3f::new(0.0, 0.0, 0.0)), 1);
+        assert_eq!(scene.label(Vec3f::new(4.8, 0.0, 0.0)), 2); // deeper, smaller label
+        assert_eq!(scene.label(Vec3f::new(20.0, 0.0, 0.0)), 0);
+    }
+
+    #[test]
+    fn overlap_policies() {
+        let labels = || [(1, (-0.5, 7)), (0, (-0.1, 9)), (2, (-0.5, 7))].into_iter();
+        assert_eq!(Overlap::First.resolve(labels()), Some(9));
+        assert_eq!(Overlap::Nearest.resolve(labels()), Some(7));
+        assert_eq!(Overlap::Label(100).resolve(labels()), Some(100));
+        assert_eq!(Overlap::Label(100).resolve(labels().skip(2)), Some(7));
+        assert_eq!(Overlap::First.resolve(labels().take(0)), None);
+    }
+
+    #[test]
+    fn signed_distance() {
+        let white = SolidColor::new(Vec3f::new(1.0, 1.0, 1.0));
+        let spheres = [(0.0, 5.0), (6.0, 2.0), (20.0, 1.0), (-12.0, 3.0)];
+        let mut scene = ObjectsScene::new();
+        for (x, r) in spheres {
+            let sphere = Box::new(Sphere::new(Vec3f::new(x, 0.0, 0.0), r));
+            scene.add(SDFObject::new(sphere, white.clone(), 1));
+        }
+        scene.build_bvh();
+
+        for x in [-20.0, -9.0, 0.0, 3.0, 7.0, 12.0, 30.0] {
+            let p = Vec3f::new(x, 1.0, 0.0);
+            let expected = spheres
+                .map(|(c, r)| (p - Vec3f::new(c, 0.0, 0.0)).norm() - r)
+                .into_iter()
+                .fold(f32::INFINITY, f32::min);
+            assert!((scene.signed_distance(p, f32::INFINITY) - expected).abs() < 1e-5);
+            assert_eq!(scene.signed_distance(p, 1.0), expected.min(1.0));
+        }
+    }
+}
@@ -1,10 +1,19 @@
-use super::{renderer::Image, Renderer};
-use image::{self, ImageError};
+use super::{Image, Renderer};
 use indicatif::ProgressIterator;
-use std::{fs::File, io::BufWriter, time::Instant};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Seek, Write},
+    time::Instant,
+};
 use tiff::{
-    encoder::{colortype, TiffEncoder},
-    TiffError,
+    decoder::{Decoder, DecodingResult},
+    encoder::{
+        colortype::{self, ColorType},
+        TiffEncoder, TiffValue,
+    },
+    tags::Tag,
+    ColorType as TiffColorType, TiffError,
 };
 
 pub struct TiffWriter {
@@ -22,13 +31,15 @@ impl TiffWriter {
         self.verbose = flag;
     }
 
-    pub fn write_images(self, dir: &str) -> Result<(), ImageError> {
+    pub fn write_images(self, dir: &str) -> Result<(), TiffError> {
         self.apply(|a| a.write_images_impl(dir))
     }
 
-    fn write_images_impl(self, dir: &str) -> Result<(), ImageError> {
+    fn write_images_impl(self, dir: &str) -> Result<(), TiffError> {
         for (i, img) in self.iter().enumerate() {
-            img.save(format!("{dir}/{i}.tif"))?;
+            let file = File::create(format!("{dir}/{i}.tif"))?;
+            let mut tiff = TiffEncoder::new(BufWriter::new(file))?;
+            write_frame(&mut tiff, &img, None)?;
         }
         Ok(())
     }
@@ -38,13 +49,7 @@ impl TiffWriter {
     }
 
     pub fn write_image_impl(self, fname: &str) -> Result<(), TiffError> {
-        let file = File::create(fname)?;
-        let w = &mut BufWriter::new(file); // always seekable
-        let mut tiff = TiffEncoder::new(w)?;
-        for img in self.iter() {
-            tiff.write_image::<colortype::Gray8>(img.width(), img.height(), &img)?;
-        }
-        Ok(())
+        write_stack(fname, self.iter())
     }
 
     fn iter<'a>(&'a self) -> Box<dyn ExactSizeIterator<Item = Image> + 'a> {
@@ -77,3 +82,112 @@ impl TiffWriter {
         }
     }
 }
+
+/// Write slices as a single tiff, multi-channel ones as a hyperstack.
+pub fn write_stack(
+    fname: &str,
+    stack: impl ExactSizeIterator<Item = Image>,
+) -> Result<(), TiffError> {
+    let file = File::create(fname)?;
+    let w = &mut BufWriter::new(file); // always seekable
+    let mut tiff = TiffEncoder::new(w)?;
+    let slices = stack.len();
+    for (i, img) in stack.enumerate() {
+        let description = match &img {
+            Image::Channels(channels) if i == 0 => Some(hyperstack(channels.len(), slices)),
+            _ => None,
+        };
+        write_frame(&mut tiff, &img, description.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Read a gray stack, e.g. a PSF, as its size along x, y and z and its values
+/// slice by slice.
+pub fn read_stack(fname: &str) -> Result<([usize; 3], Vec<f32>), Box<dyn Error>> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(fname)?))?;
+    let (w, h) = decoder.dimensions()?;
+    let mut values = vec![];
+    let mut slices = 0;
+    loop {
+        if !matches!(decoder.colortype()?, TiffColorType::Gray(_)) {
+            return Err("stack only support gray images".into());
+        }
+        if decoder.dimensions()? != (w, h) {
+            return Err("slices of the stack differ in size".into());
+        }
+        match decoder.read_image()? {
+            DecodingResult::U8(a) => values.extend(a.into_iter().map(|v| v as f32)),
+            DecodingResult::U16(a) => values.extend(a.into_iter().map(|v| v as f32)),
+            DecodingResult::U32(a) => values.extend(a.into_iter().map(|v| v as f32)),
+            DecodingResult::F32(a) => values.extend(a),
+            DecodingResult::F64(a) => values.extend(a.into_iter().map(|v| v as f32)),
+            _ => return Err("stack only support u8/u16/u32/f32/f64 voxels".into()),
+        }
+        slices += 1;
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image()?;
+    }
+    Ok(([w as usize, h as usize, slices], values))
+}
+
+/// Write a slice as a page, with the color type matching its depth, or as a
+/// page per channel. The description is put on the first page.
+fn write_frame<W: Write + Seek>(
+    tiff: &mut TiffEncoder<W>,
+    img: &Image,
+    description: Option<&str>,
+) -> Result<(), TiffError> {
+    let (w, h) = (img.width(), img.height());
+    match img {
+        Image::Gray8(img) => write_page::<_, colortype::Gray8>(tiff, w, h, img, description),
+        Image::Gray16(img) => write_page::<_, colortype::Gray16>(tiff, w, h, img, description),
+        Image::Gray32(img) => write_page::<_, colortype::Gray32>(tiff, w, h, img, description),
+        Image::Gray32Float(img) => {
+            write_page::<_, colortype::Gray32Float>(tiff, w, h, img, description)
+        }
+        Image::Rgb8(img) => write_page::<_, colortype::RGB8>(tiff, w, h, img, description),
+        Image::Rgb16(img) => write_page::<_, colortype::RGB16>(tiff, w, h, img, description),
+        Image::Rgb32(img) => write_page::<_, colortype::RGB32>(tiff, w, h, img, description),
+        Image::Rgb32Float(img) => {
+            write_page::<_, colortype::RGB32Float>(tiff, w, h, img, description)
+        }
+        Image::Channels(channels) => {
+            let mut description = description;
+            for channel in channels {
+                write_frame(tiff, channel, description.take())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_page<W: Write + Seek, C: ColorType>(
+    tiff: &mut TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    data: &[C::Inner],
+    description: Option<&str>,
+) -> Result<(), TiffError>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut image = tiff.new_image::<C>(width, height)?;
+    if let Some(description) = description {
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, description)?;
+    }
+    image.write_data(data)
+}
+
+/// ImageJ description of a stack of multi-channel slices, so that it opens as
+/// a hyperstack, with channels interleaved slice by slice.
+fn hyperstack(channels: usize, slices: usize) -> String {
+    format!(
+        "ImageJ=1.11a\nimages={}\nchannels={channels}\nslices={slices}\nhyperstack=true\nmode=grayscale\n",
+        channels * slices
+    )
+}
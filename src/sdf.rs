@@ -1,9 +1,11 @@
 mod combination;
+mod ellipsoid;
 mod round_cone;
 mod sdf;
 mod sphere;
 
 pub use combination::Min;
+pub use ellipsoid::Ellipsoid;
 pub use round_cone::RoundCone;
 pub use sdf::SDF;
 pub use sphere::Sphere;
@@ -0,0 +1,52 @@
+use super::SDF;
+use crate::vec::{self, Vec3f};
+
+pub struct Ellipsoid {
+    center: Vec3f,
+    axes: [Vec3f; 3], // orthonormal
+    radii: Vec3f,
+    bounding_box: (Vec3f, Vec3f),
+}
+
+impl Ellipsoid {
+    pub fn new(center: Vec3f, axes: [Vec3f; 3], radii: Vec3f) -> Self {
+        let [a, b, c] = axes;
+        let half = |x: f32, y: f32, z: f32| {
+            f32::sqrt((radii.x * x).powi(2) + (radii.y * y).powi(2) + (radii.z * z).powi(2))
+        };
+        let extent = Vec3f::new(
+            half(a.x, b.x, c.x),
+            half(a.y, b.y, c.y),
+            half(a.z, b.z, c.z),
+        );
+        Ellipsoid {
+            center,
+            axes,
+            radii,
+            bounding_box: (center - extent, center + extent),
+        }
+    }
+}
+
+impl SDF for Ellipsoid {
+    fn signed_distance(&self, p: Vec3f) -> f32 {
+        let p = p - self.center;
+        let [a, b, c] = self.axes;
+        let local = Vec3f::new(vec::dot(p, a), vec::dot(p, b), vec::dot(p, c));
+        sd_ellipsoid(local, self.radii)
+    }
+
+    fn bounding_box(&self) -> (Vec3f, Vec3f) {
+        self.bounding_box
+    }
+}
+
+fn sd_ellipsoid(p: Vec3f, r: Vec3f) -> f32 {
+    // bound, not exact, see also: https://iquilezles.org/articles/ellipsoids/
+    let k0 = (p / r).norm();
+    let k1 = (p / (r * r)).norm();
+    if k1 == 0.0 {
+        return -f32::min(r.x, f32::min(r.y, r.z));
+    }
+    k0 * (k0 - 1.0) / k1
+}
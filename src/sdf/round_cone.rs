@@ -73,6 +73,26 @@ fn sd_round_cone(p: Vec3f, a: Vec3f, b: Vec3f, ra: f32, rb: f32) -> f32 {
 fn proj_p_to_line(p: Vec3f, a: Vec3f, b: Vec3f) -> f32 {
     let ap = p - a;
     let ab = b - a;
-    let ao = a + ab * (vec::dot(ap, ab) / vec::dot(ab, ab)); // proj P to AB in O
-    vec::dot(ao, ab).signum() * ao.norm() / ab.norm() // O = A + k * (AB)
+    vec::dot(ap, ab) / vec::dot(ab, ab) // proj P to AB in O = A + k * (AB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v_axis() {
+        // away from the origin, where v used to be measured from
+        let cone = RoundCone::new(
+            Vec3f::new(10.0, 5.0, 0.0),
+            2.0,
+            Vec3f::new(14.0, 5.0, 0.0),
+            1.0,
+        );
+        let v = |x| cone.hit(Vec3f::new(x, 5.5, 0.0)).v;
+        assert_eq!(
+            [v(9.0), v(10.0), v(12.0), v(14.0), v(14.5)],
+            [0.0, 0.0, 0.5, 1.0, 1.0]
+        );
+    }
 }